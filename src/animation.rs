@@ -0,0 +1,366 @@
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::sniff::MediaType;
+
+/// Frame information for an image file. Static images have a single frame and no duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Animation {
+    pub frame_count: u32,
+    pub duration_ms: u64,
+}
+
+impl Animation {
+    pub const STATIC: Animation = Animation {
+        frame_count: 1,
+        duration_ms: 0,
+    };
+
+    pub fn is_animated(&self) -> bool {
+        self.frame_count > 1
+    }
+}
+
+/// Reads just enough of a gif, png or webp file to count its frames. Other image types can't be animated so are
+/// always static.
+pub fn animation_info(filepath: &Path, media_type: MediaType) -> Result<Animation, Error> {
+    let parser = match media_type {
+        MediaType::Gif => gif_animation,
+        MediaType::Png => png_animation,
+        MediaType::Webp => webp_animation,
+        _ => return Ok(Animation::STATIC),
+    };
+
+    let mut reader = BufReader::new(File::open(filepath)?);
+    parser(&mut reader)
+}
+
+/// Browsers play gif frames with a delay of 0 or 1 hundredths of a second at 100ms, so we count them the same way.
+const GIF_MINIMUM_DELAY_CS: u64 = 2;
+const GIF_DEFAULT_DELAY_CS: u64 = 10;
+
+fn gif_animation<R: Read + Seek>(reader: &mut R) -> Result<Animation, Error> {
+    let mut header = [0u8; 13];
+    reader.read_exact(&mut header)?;
+    if &header[0..3] != b"GIF" {
+        return Err(invalid_data("not a gif file"));
+    }
+    skip_gif_colour_table(reader, header[10])?;
+
+    let mut frame_count = 0;
+    let mut duration_cs = 0;
+    // The delay from the most recent graphic control extension applies to the next image.
+    let mut delay_cs = 0;
+    loop {
+        match read_u8(reader) {
+            // image descriptor
+            Ok(0x2C) => {
+                let mut descriptor = [0u8; 9];
+                reader.read_exact(&mut descriptor)?;
+                skip_gif_colour_table(reader, descriptor[8])?;
+                // LZW minimum code size, then the image data
+                read_u8(reader)?;
+                skip_gif_sub_blocks(reader)?;
+
+                frame_count += 1;
+                duration_cs += if delay_cs < GIF_MINIMUM_DELAY_CS {
+                    GIF_DEFAULT_DELAY_CS
+                } else {
+                    delay_cs
+                };
+                delay_cs = 0;
+            }
+            // extension
+            Ok(0x21) => {
+                let label = read_u8(reader)?;
+                if label == 0xF9 {
+                    let mut control = [0u8; 6];
+                    reader.read_exact(&mut control)?;
+                    delay_cs = u16::from_le_bytes([control[2], control[3]]) as u64;
+                    // control[5] is the block terminator
+                } else {
+                    skip_gif_sub_blocks(reader)?;
+                }
+            }
+            // trailer, or a file that was cut short. Either way we have counted what is there.
+            Ok(0x3B) => break,
+            Err(Error::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Ok(other) => return Err(invalid_data(&format!("unknown gif block {other:#x}"))),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(frames(frame_count, duration_cs * 10))
+}
+
+fn skip_gif_colour_table<R: Read + Seek>(reader: &mut R, flags: u8) -> Result<(), Error> {
+    if flags & 0x80 != 0 {
+        let size = 3 * (1i64 << ((flags & 0x07) + 1));
+        reader.seek(SeekFrom::Current(size))?;
+    }
+    Ok(())
+}
+
+fn skip_gif_sub_blocks<R: Read + Seek>(reader: &mut R) -> Result<(), Error> {
+    loop {
+        let size = read_u8(reader)?;
+        if size == 0 {
+            return Ok(());
+        }
+        reader.seek(SeekFrom::Current(size as i64))?;
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// An animated png has an `acTL` chunk before the image data, each frame is described by a `fcTL` chunk.
+fn png_animation<R: Read + Seek>(reader: &mut R) -> Result<Animation, Error> {
+    let mut signature = [0u8; 8];
+    reader.read_exact(&mut signature)?;
+    if signature != PNG_SIGNATURE {
+        return Err(invalid_data("not a png file"));
+    }
+
+    let mut animated = false;
+    let mut frame_count = 0;
+    let mut duration_ms = 0;
+    loop {
+        let mut chunk_header = [0u8; 8];
+        reader.read_exact(&mut chunk_header)?;
+        let length = u32::from_be_bytes([
+            chunk_header[0],
+            chunk_header[1],
+            chunk_header[2],
+            chunk_header[3],
+        ]);
+        let mut consumed = 0;
+        match &chunk_header[4..8] {
+            b"acTL" => animated = true,
+            b"fcTL" => {
+                let mut control = [0u8; 26];
+                reader.read_exact(&mut control)?;
+                consumed = control.len() as i64;
+                let numerator = u16::from_be_bytes([control[20], control[21]]) as u64;
+                let denominator = match u16::from_be_bytes([control[22], control[23]]) {
+                    0 => 100,
+                    d => d as u64,
+                };
+                frame_count += 1;
+                duration_ms += numerator * 1000 / denominator;
+            }
+            // Without an animation control chunk before the image data this is a normal png.
+            b"IDAT" if !animated => return Ok(Animation::STATIC),
+            b"IEND" => break,
+            _ => {}
+        }
+        // skip the rest of the chunk and the crc
+        reader.seek(SeekFrom::Current(length as i64 - consumed + 4))?;
+    }
+
+    Ok(frames(frame_count, duration_ms))
+}
+
+/// Animated webp files use the extended format, with a flag in the `VP8X` chunk and one `ANMF` chunk per frame.
+fn webp_animation<R: Read + Seek>(reader: &mut R) -> Result<Animation, Error> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+        return Err(invalid_data("not a webp file"));
+    }
+
+    let mut frame_count = 0;
+    let mut duration_ms = 0;
+    loop {
+        let mut chunk_header = [0u8; 8];
+        match reader.read_exact(&mut chunk_header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let length = u32::from_le_bytes([
+            chunk_header[4],
+            chunk_header[5],
+            chunk_header[6],
+            chunk_header[7],
+        ]);
+        let mut consumed = 0;
+        match &chunk_header[0..4] {
+            b"VP8X" => {
+                let flags = read_u8(reader)?;
+                consumed = 1;
+                if flags & 0x02 == 0 {
+                    return Ok(Animation::STATIC);
+                }
+            }
+            b"ANMF" => {
+                let mut frame = [0u8; 16];
+                reader.read_exact(&mut frame)?;
+                consumed = frame.len() as i64;
+                frame_count += 1;
+                duration_ms += u32::from_le_bytes([frame[12], frame[13], frame[14], 0]) as u64;
+            }
+            // simple lossy or lossless files only contain a single image
+            b"VP8 " | b"VP8L" if frame_count == 0 => return Ok(Animation::STATIC),
+            _ => {}
+        }
+        // chunks are padded to an even length
+        let padded = length as i64 + (length as i64 & 1);
+        reader.seek(SeekFrom::Current(padded - consumed))?;
+    }
+
+    Ok(frames(frame_count, duration_ms))
+}
+
+fn frames(frame_count: u32, duration_ms: u64) -> Animation {
+    if frame_count <= 1 {
+        return Animation::STATIC;
+    }
+    Animation {
+        frame_count,
+        duration_ms,
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, Error> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::Io(std::io::Error::new(
+        ErrorKind::InvalidData,
+        message.to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::animation::{gif_animation, png_animation, webp_animation, Animation};
+
+    fn gif(delays: &[u16]) -> Vec<u8> {
+        let mut result = b"GIF89a".to_vec();
+        // 1x1 with a two colour global table
+        result.extend([1, 0, 1, 0, 0x80, 0, 0]);
+        result.extend([0, 0, 0, 255, 255, 255]);
+        for delay in delays {
+            result.extend([0x21, 0xF9, 4, 0]);
+            result.extend(delay.to_le_bytes());
+            result.extend([0, 0]);
+            result.extend([0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+            result.extend([2, 2, 0x4C, 0x01, 0]);
+        }
+        result.push(0x3B);
+        result
+    }
+
+    fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut result = (data.len() as u32).to_be_bytes().to_vec();
+        result.extend(kind);
+        result.extend(data);
+        // the crc isn't checked
+        result.extend([0, 0, 0, 0]);
+        result
+    }
+
+    fn png(delays: &[(u16, u16)]) -> Vec<u8> {
+        let mut result = b"\x89PNG\r\n\x1a\n".to_vec();
+        result.extend(png_chunk(b"IHDR", &[0; 13]));
+        if !delays.is_empty() {
+            let mut control = (delays.len() as u32).to_be_bytes().to_vec();
+            control.extend([0, 0, 0, 0]);
+            result.extend(png_chunk(b"acTL", &control));
+        }
+        for (numerator, denominator) in delays {
+            let mut control = vec![0; 20];
+            control.extend(numerator.to_be_bytes());
+            control.extend(denominator.to_be_bytes());
+            control.extend([0, 0]);
+            result.extend(png_chunk(b"fcTL", &control));
+            result.extend(png_chunk(b"IDAT", &[1, 2, 3]));
+        }
+        if delays.is_empty() {
+            result.extend(png_chunk(b"IDAT", &[1, 2, 3]));
+        }
+        result.extend(png_chunk(b"IEND", &[]));
+        result
+    }
+
+    fn webp_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut result = kind.to_vec();
+        result.extend((data.len() as u32).to_le_bytes());
+        result.extend(data);
+        if data.len() % 2 == 1 {
+            result.push(0);
+        }
+        result
+    }
+
+    fn webp(durations: &[u32]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        if durations.is_empty() {
+            body.extend(webp_chunk(b"VP8L", &[0x2F, 0, 0, 0, 0]));
+        } else {
+            body.extend(webp_chunk(b"VP8X", &[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+            body.extend(webp_chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]));
+            for duration in durations {
+                let mut frame = vec![0; 12];
+                frame.extend(&duration.to_le_bytes()[0..3]);
+                frame.push(0);
+                frame.extend(webp_chunk(b"VP8L", &[0x2F, 0, 0, 0, 0]));
+                body.extend(webp_chunk(b"ANMF", &frame));
+            }
+        }
+        let mut result = b"RIFF".to_vec();
+        result.extend((body.len() as u32).to_le_bytes());
+        result.extend(body);
+        result
+    }
+
+    #[test]
+    fn gif_static() {
+        let result = gif_animation(&mut Cursor::new(gif(&[0]))).unwrap();
+        assert_eq!(result, Animation::STATIC);
+    }
+
+    #[test]
+    fn gif_animated() {
+        let result = gif_animation(&mut Cursor::new(gif(&[50, 25, 0]))).unwrap();
+        assert!(result.is_animated());
+        assert_eq!(result.frame_count, 3);
+        // the zero delay frame is played at the browser default
+        assert_eq!(result.duration_ms, 850);
+    }
+
+    #[test]
+    fn png_static() {
+        let result = png_animation(&mut Cursor::new(png(&[]))).unwrap();
+        assert_eq!(result, Animation::STATIC);
+    }
+
+    #[test]
+    fn png_animated() {
+        let result = png_animation(&mut Cursor::new(png(&[(1, 10), (250, 0)]))).unwrap();
+        assert_eq!(result.frame_count, 2);
+        assert_eq!(result.duration_ms, 2600);
+    }
+
+    #[test]
+    fn webp_static() {
+        let result = webp_animation(&mut Cursor::new(webp(&[]))).unwrap();
+        assert_eq!(result, Animation::STATIC);
+    }
+
+    #[test]
+    fn webp_animated() {
+        let result = webp_animation(&mut Cursor::new(webp(&[40, 40, 120]))).unwrap();
+        assert_eq!(result.frame_count, 3);
+        assert_eq!(result.duration_ms, 200);
+    }
+}
@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use crate::culling::Culling;
+use crate::error::Error;
+use crate::favourites::Favourites;
+use crate::image_info::ImageInfo;
+use crate::store::Store;
+use crate::tags::Tags;
+
+/// Everything picture browser keeps about the files rather than reads from them, and where it is kept.
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    pub store: Store,
+    pub favourites: Favourites,
+    pub tags: Tags,
+    pub culling: Culling,
+}
+
+impl Annotations {
+    pub fn load(roots: &[String], data_dir: Option<&Path>) -> Annotations {
+        let store = Store::new(roots, data_dir);
+        Annotations {
+            favourites: Favourites::load(&store, roots),
+            tags: Tags::load(&store, roots),
+            culling: Culling::load(&store, roots),
+            store,
+        }
+    }
+
+    /// Copies what's known about each file onto a freshly scanned list.
+    pub fn mark(&mut self, images: &mut [ImageInfo]) {
+        self.favourites.mark(images);
+        self.tags.mark(&self.store, images);
+        self.culling.mark(images);
+    }
+
+    /// Moves everything known about a file to its new path from the root.
+    pub fn moved(&mut self, image: &ImageInfo, old_path: &str) -> Result<(), Error> {
+        self.favourites.moved(&self.store, image, old_path)?;
+        self.tags.moved(&self.store, image, old_path)?;
+        self.culling.moved(&self.store, image, old_path)
+    }
+}
@@ -0,0 +1,206 @@
+use serde::Serialize;
+
+use crate::image_info::ImageInfo;
+
+pub const DEFAULT_TILE: u32 = 160;
+pub const MIN_TILE: u32 = 48;
+pub const MAX_TILE: u32 = 512;
+/// The space between the tiles, and around the edge of the sheet.
+pub const TILE_GAP: u32 = 4;
+/// The height of a group header in the sheet, including its margins.
+pub const SHEET_HEADER_HEIGHT: u32 = 30;
+
+/// Where one file goes on the sheet. The position and size are of the thumbnail fitted into its square, so it keeps
+/// its shape.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Tile {
+    pub url: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub is_video: bool,
+}
+
+/// A group header, across the full width above the group's first row.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SheetHeader {
+    pub label: String,
+    pub y: u32,
+    /// The first image in the group.
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContactSheet {
+    /// The size of each square, which may be smaller than asked for so at least one fits.
+    pub tile: u32,
+    pub columns: u32,
+    pub width: u32,
+    pub height: u32,
+    /// The size to ask `/thumb/` for, so every tile shares the same cached thumbnails.
+    pub thumbnail: u32,
+    pub headers: Vec<SheetHeader>,
+    /// In the same order as the images.
+    pub tiles: Vec<Tile>,
+}
+
+/// Lays the images out in rows of squares across a page `width` pixels wide, in the same order as the page. Each
+/// group header starts a new row.
+pub fn contact_sheet(images: &[ImageInfo], tile: u32, width: u32) -> ContactSheet {
+    let tile = tile
+        .clamp(MIN_TILE, MAX_TILE)
+        .min(width.saturating_sub(2 * TILE_GAP).max(MIN_TILE));
+    let step = tile + TILE_GAP;
+    let columns = (width.saturating_sub(TILE_GAP) / step).max(1);
+
+    let mut headers = Vec::new();
+    let mut tiles = Vec::with_capacity(images.len());
+    let mut column = 0;
+    // The top of the current row.
+    let mut y = TILE_GAP;
+    for image in images {
+        if let Some(label) = &image.header {
+            if column > 0 {
+                y += step;
+                column = 0;
+            }
+            headers.push(SheetHeader {
+                label: label.clone(),
+                y,
+                url: image.url.clone(),
+            });
+            y += SHEET_HEADER_HEIGHT;
+        } else if column == columns {
+            y += step;
+            column = 0;
+        }
+
+        let (fitted_width, fitted_height) = fit(image.width, image.height, tile);
+        tiles.push(Tile {
+            url: image.url.clone(),
+            x: TILE_GAP + column * step + (tile - fitted_width) / 2,
+            y: y + (tile - fitted_height) / 2,
+            width: fitted_width,
+            height: fitted_height,
+            is_video: image.is_video,
+        });
+        column += 1;
+    }
+    let height = if tiles.is_empty() { y } else { y + step };
+
+    ContactSheet {
+        tile,
+        columns,
+        width: TILE_GAP + columns * step,
+        height,
+        thumbnail: thumbnail_size(tile),
+        headers,
+        tiles,
+    }
+}
+
+/// The largest size with the same shape that fits in the square. A file whose size isn't known fills it.
+fn fit(width: u64, height: u64, tile: u32) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (tile, tile);
+    }
+    let tile = u64::from(tile);
+    let (width, height) = if width >= height {
+        (tile, (height * tile / width).max(1))
+    } else {
+        ((width * tile / height).max(1), tile)
+    };
+    (width as u32, height as u32)
+}
+
+/// Rounds up to a power of two so there are only a few sizes of thumbnail in the cache, whatever size the tiles are.
+/// Twice the tile size is allowed for screens with more pixels than css pixels.
+pub fn thumbnail_size(requested: u32) -> u32 {
+    requested
+        .clamp(MIN_TILE, 2 * MAX_TILE)
+        .next_power_of_two()
+        .min(2 * MAX_TILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::contact_sheet::{contact_sheet, thumbnail_size, SHEET_HEADER_HEIGHT, TILE_GAP};
+    use crate::image_info::ImageInfo;
+    use crate::tree::tests::simple_image;
+
+    fn image(url: &str, width: u64, height: u64, header: Option<&str>) -> ImageInfo {
+        let mut image = simple_image(height);
+        image.url = url.to_string();
+        image.width = width;
+        image.header = header.map(str::to_string);
+        image
+    }
+
+    #[test]
+    fn tile_positions() {
+        let images = vec![
+            image("a", 200, 100, Some("March 2024")),
+            image("b", 100, 200, None),
+            image("c", 100, 100, None),
+            image("d", 0, 0, Some("April 2024")),
+        ];
+        // Room for two 100px tiles and the gaps, not three.
+        let sheet = contact_sheet(&images, 100, 2 * 100 + 3 * TILE_GAP + 50);
+        assert_eq!(sheet.columns, 2);
+        assert_eq!(sheet.tile, 100);
+
+        let step = 100 + TILE_GAP;
+        let first_row = TILE_GAP + SHEET_HEADER_HEIGHT;
+        let positions: Vec<(&str, u32, u32, u32, u32)> = sheet
+            .tiles
+            .iter()
+            .map(|t| (t.url.as_str(), t.x, t.y, t.width, t.height))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                // Wide, centred from top to bottom.
+                ("a", TILE_GAP, first_row + 25, 100, 50),
+                // Tall, centred from side to side.
+                ("b", TILE_GAP + step + 25, first_row, 50, 100),
+                ("c", TILE_GAP, first_row + step, 100, 100),
+                // A new group starts a new row after its header, and an unknown size fills the square.
+                (
+                    "d",
+                    TILE_GAP,
+                    first_row + 2 * step + SHEET_HEADER_HEIGHT,
+                    100,
+                    100
+                ),
+            ]
+        );
+
+        let headers: Vec<(&str, u32, &str)> = sheet
+            .headers
+            .iter()
+            .map(|h| (h.label.as_str(), h.y, h.url.as_str()))
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                ("March 2024", TILE_GAP, "a"),
+                ("April 2024", first_row + 2 * step, "d")
+            ]
+        );
+        assert_eq!(sheet.height, first_row + 3 * step + SHEET_HEADER_HEIGHT);
+
+        // Narrower than one tile still gets a column.
+        let narrow = contact_sheet(&images, 100, 60);
+        assert_eq!(narrow.columns, 1);
+        assert_eq!(narrow.tile, 52);
+    }
+
+    #[test]
+    fn thumbnail_sizes() {
+        assert_eq!(thumbnail_size(10), 64);
+        assert_eq!(thumbnail_size(160), 256);
+        assert_eq!(thumbnail_size(256), 256);
+        assert_eq!(thumbnail_size(5000), 1024);
+    }
+}
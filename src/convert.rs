@@ -0,0 +1,153 @@
+use std::env;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use image::{ImageFormat, ImageReader};
+use log::info;
+use which::which;
+
+use crate::error::Error;
+use crate::store::write_then_rename;
+
+/// Returns the path to a png version of the source file, converting it if there isn't already an up to date copy in
+/// the cache folder.
+pub fn converted_path(source: &Path) -> Result<PathBuf, Error> {
+    let target = cache_path(source, "png")?;
+    if target.exists() {
+        return Ok(target);
+    }
+
+    info!("Converting {:?} to png", source);
+    // The contents decide the format rather than the extension, in case the file is misnamed.
+    let image = ImageReader::open(source)?.with_guessed_format()?.decode()?;
+
+    write_then_rename(&target, |partial| {
+        Ok(image.save_with_format(partial, ImageFormat::Png)?)
+    })?;
+
+    Ok(target)
+}
+
+/// Returns the path to a still frame from near the start of a video, using ffmpeg to grab one if there isn't already
+/// a copy in the cache folder.
+pub fn poster_path(source: &Path, duration_ms: u64) -> Result<PathBuf, Error> {
+    let target = cache_path(source, "jpg")?;
+    if target.exists() {
+        return Ok(target);
+    }
+
+    if which("ffmpeg").is_err() {
+        return Err(Error::MissingFFMpeg);
+    }
+
+    // Lots of videos fade in from black, so skip a second in. Or a tenth of the way through very short clips.
+    let offset_ms = (duration_ms / 10).min(1000);
+
+    info!("Creating a poster frame for {:?}", source);
+    write_then_rename(&target, |partial| {
+        let status = Command::new("ffmpeg")
+            .args(["-v", "quiet", "-y", "-ss"])
+            .arg(format!("{}.{:03}", offset_ms / 1000, offset_ms % 1000))
+            .arg("-i")
+            .arg(source)
+            .args(["-frames:v", "1", "-f", "image2", "-c:v", "mjpeg"])
+            .arg(partial)
+            .status()?;
+
+        if !status.success() || !partial.exists() {
+            return Err(Error::PosterFrame(source.to_string_lossy().to_string()));
+        }
+        Ok(())
+    })?;
+
+    Ok(target)
+}
+
+/// Returns the path to a jpeg no bigger than `size` on either side, for the contact sheet. Videos use their poster
+/// frame. Cached the same way as conversions.
+pub fn thumbnail_path(
+    source: &Path,
+    is_video: bool,
+    duration_ms: u64,
+    size: u32,
+) -> Result<PathBuf, Error> {
+    let target = cache_path(source, &format!("{size}.jpg"))?;
+    if target.exists() {
+        return Ok(target);
+    }
+
+    let still = if is_video {
+        poster_path(source, duration_ms)?
+    } else {
+        source.to_path_buf()
+    };
+    let image = ImageReader::open(&still)?.with_guessed_format()?.decode()?;
+
+    // jpeg has no transparency, so anything see through comes out black.
+    let thumbnail = image.thumbnail(size, size).to_rgb8();
+    write_then_rename(&target, |partial| {
+        Ok(thumbnail.save_with_format(partial, ImageFormat::Jpeg)?)
+    })?;
+
+    Ok(target)
+}
+
+/// A path in the cache folder for a file generated from the source. The name includes the modified time and size of
+/// the source so a changed file gets a new cache entry rather than a stale one.
+pub fn cache_path(source: &Path, extension: &str) -> Result<PathBuf, Error> {
+    let metadata = fs::metadata(source)?;
+
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    metadata.modified()?.hash(&mut hasher);
+
+    let folder = cache_folder();
+    if !folder.exists() {
+        fs::create_dir_all(&folder)?;
+    }
+
+    Ok(folder.join(format!("{:016x}.{}", hasher.finish(), extension)))
+}
+
+fn cache_folder() -> PathBuf {
+    env::temp_dir().join("picture_browser")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+    use std::thread;
+
+    use image::RgbImage;
+    use tempfile::tempdir;
+
+    use crate::convert::converted_path;
+
+    #[test]
+    fn racing_conversions() {
+        let folder = tempdir().unwrap();
+        let source = folder.path().join("racing.bmp");
+        RgbImage::new(300, 200).save(&source).unwrap();
+
+        // Every request for the same file at once gets a whole png.
+        let barrier = Barrier::new(8);
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        converted_path(&source)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for result in results {
+            let png = image::open(result.unwrap()).unwrap();
+            assert_eq!((png.width(), png.height()), (300, 200));
+        }
+    }
+}
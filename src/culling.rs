@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::annotations::Annotations;
+use crate::error::Error;
+use crate::file_actions::FileActions;
+use crate::image_info::ImageInfo;
+use crate::store::{PerRoot, Store};
+
+pub const CULLING_FILE: &str = "culling.json";
+/// Written to the same folder as the decisions when the rejects are applied as a list.
+pub const REJECTS_FILE: &str = "rejects.txt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Keep,
+    Reject,
+}
+
+impl std::str::FromStr for Decision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Decision::Keep),
+            "reject" => Ok(Decision::Reject),
+            _ => Err(Error::InvalidName(format!(
+                "a file can be kept or rejected, not {s:?}"
+            ))),
+        }
+    }
+}
+
+/// What has been decided about each file while culling, under each root by the path from the root.
+#[derive(Debug, Clone)]
+pub struct Culling {
+    by_root: PerRoot<BTreeMap<String, Decision>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CullSummary {
+    pub keep: usize,
+    pub reject: usize,
+    /// Full paths, in order.
+    pub rejects: Vec<String>,
+}
+
+/// Where one rejected file went when the decisions were applied.
+#[derive(Debug, Clone, Serialize)]
+pub struct Applied {
+    pub from: String,
+    pub to: String,
+}
+
+impl Default for Culling {
+    fn default() -> Culling {
+        Culling {
+            by_root: PerRoot::new(CULLING_FILE),
+        }
+    }
+}
+
+impl Culling {
+    pub fn load(store: &Store, roots: &[String]) -> Culling {
+        Culling {
+            by_root: PerRoot::load(store, roots, CULLING_FILE),
+        }
+    }
+
+    /// Sets the `decision` field of each image, after a scan.
+    pub fn mark(&self, images: &mut [ImageInfo]) {
+        for image in images.iter_mut() {
+            image.decision = self.get(image);
+        }
+    }
+
+    fn get(&self, image: &ImageInfo) -> Option<Decision> {
+        self.by_root
+            .get(&image.root)?
+            .get(&image.relative_path)
+            .copied()
+    }
+
+    /// Keeps or rejects a file, or with None forgets the decision so it can be undone. Saved straight away.
+    pub fn decide(
+        &mut self,
+        store: &Store,
+        image: &mut ImageInfo,
+        decision: Option<Decision>,
+    ) -> Result<(), Error> {
+        self.by_root.update(store, &image.root, |decisions| {
+            match decision {
+                Some(decision) => decisions.insert(image.relative_path.clone(), decision),
+                None => decisions.remove(&image.relative_path),
+            };
+            Ok(())
+        })?;
+        image.decision = decision;
+        Ok(())
+    }
+
+    /// Keeps the decision about a file that has been moved or renamed.
+    pub fn moved(&mut self, store: &Store, image: &ImageInfo, old_path: &str) -> Result<(), Error> {
+        self.by_root
+            .rename_key(store, &image.root, old_path, &image.relative_path)
+    }
+
+    /// Counts the decisions about the images given, so only ones still on disk.
+    pub fn summary(images: &[ImageInfo]) -> CullSummary {
+        let mut summary = CullSummary::default();
+        for image in images {
+            match image.decision {
+                Some(Decision::Keep) => summary.keep += 1,
+                Some(Decision::Reject) => {
+                    summary.reject += 1;
+                    summary.rejects.push(image.source.clone());
+                }
+                None => {}
+            }
+        }
+        summary.rejects.sort();
+        summary
+    }
+
+    /// Writes the rejected files under each root to `rejects.txt`, one full path per line, next to the decisions.
+    /// Nothing is moved, so this works without `--allow-write`. Returns the files written.
+    pub fn write_lists(store: &Store, images: &[ImageInfo]) -> Result<Vec<String>, Error> {
+        let mut by_root: BTreeMap<&str, String> = BTreeMap::new();
+        for image in images
+            .iter()
+            .filter(|i| i.decision == Some(Decision::Reject))
+        {
+            let list = by_root.entry(&image.root).or_default();
+            list.push_str(&image.source);
+            list.push('\n');
+        }
+
+        let mut written = Vec::new();
+        for (root, list) in by_root {
+            let path = store.write(root, REJECTS_FILE, list.as_bytes())?;
+            written.push(path.to_string_lossy().to_string());
+        }
+        Ok(written)
+    }
+}
+
+/// A rejected file to move, worked out while the images are locked so the move itself can happen without them.
+#[derive(Debug, Clone)]
+pub struct RejectMove {
+    /// Where it was before the move, to find it again after.
+    pub url: String,
+    pub old_path: String,
+    pub image: ImageInfo,
+    folder: String,
+}
+
+/// The rejected files to move into `folder` under their roots, keeping the sub folders they were in.
+pub fn plan_rejects(images: &[ImageInfo], folder: &str) -> Result<Vec<RejectMove>, Error> {
+    let folder = folder.trim_matches('/');
+    if folder.is_empty() {
+        return Err(Error::InvalidName(
+            "rejects need a folder to go in".to_string(),
+        ));
+    }
+
+    Ok(images
+        .iter()
+        .filter(|i| i.decision == Some(Decision::Reject))
+        // Already moved by an earlier apply.
+        .filter(|i| !i.relative_path.starts_with(&format!("{folder}/")))
+        .map(|image| RejectMove {
+            url: image.url.clone(),
+            old_path: image.relative_path.clone(),
+            image: image.clone(),
+            folder: match Path::new(&image.relative_path).parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => {
+                    format!("{folder}/{}", parent.to_string_lossy())
+                }
+                _ => folder.to_string(),
+            },
+        })
+        .collect())
+}
+
+/// Moves the planned files. Stops at the first file that can't be moved, the ones before it stay moved and are
+/// returned along with the error.
+pub fn move_rejects(
+    actions: &FileActions,
+    moves: Vec<RejectMove>,
+) -> (Vec<RejectMove>, Result<(), Error>) {
+    let mut moved = Vec::new();
+    for mut next in moves {
+        if let Err(e) = actions.move_to(&mut next.image, &next.folder) {
+            return (moved, Err(e));
+        }
+        moved.push(next);
+    }
+    (moved, Ok(()))
+}
+
+/// Puts the moved files in place of the images they were, taking everything kept about them along so they stay
+/// rejected.
+pub fn record_moves(
+    annotations: &mut Annotations,
+    images: &mut [ImageInfo],
+    moved: Vec<RejectMove>,
+) -> Result<Vec<Applied>, Error> {
+    let mut applied = Vec::new();
+    for next in moved {
+        let Some(image) = images.iter_mut().find(|i| i.url == next.url) else {
+            continue;
+        };
+        let from = std::mem::replace(image, next.image).source;
+        annotations.moved(image, &next.old_path)?;
+        info!("Moved rejected {} to {}", from, image.source);
+        applied.push(Applied {
+            from,
+            to: image.source.clone(),
+        });
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::annotations::Annotations;
+    use crate::culling::{
+        move_rejects, plan_rejects, record_moves, Applied, Culling, Decision, REJECTS_FILE,
+    };
+    use crate::error::Error;
+    use crate::file_actions::{FileActions, TrashKind};
+    use crate::image_info::ImageInfo;
+    use crate::store::Store;
+    use crate::tree::tests::test_file;
+
+    fn apply(
+        annotations: &mut Annotations,
+        actions: &FileActions,
+        images: &mut [ImageInfo],
+        folder: &str,
+    ) -> Result<Vec<Applied>, Error> {
+        let (moved, result) = move_rejects(actions, plan_rejects(images, folder)?);
+        let applied = record_moves(annotations, images, moved)?;
+        result.map(|_| applied)
+    }
+
+    #[test]
+    fn decisions() {
+        let root = tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let roots = vec![root_path.clone()];
+        let store = Store::new(&roots, None);
+
+        let mut images = vec![
+            test_file(&root_path, "a.jpg"),
+            test_file(&root_path, "day/b.jpg"),
+            test_file(&root_path, "c.jpg"),
+        ];
+        let mut culling = Culling::load(&store, &roots);
+        culling
+            .decide(&store, &mut images[0], Some(Decision::Keep))
+            .unwrap();
+        culling
+            .decide(&store, &mut images[1], Some(Decision::Reject))
+            .unwrap();
+        culling
+            .decide(&store, &mut images[2], Some(Decision::Reject))
+            .unwrap();
+        // Undone.
+        culling.decide(&store, &mut images[2], None).unwrap();
+
+        // A fresh load, like after a restart.
+        Culling::load(&store, &roots).mark(&mut images);
+        let decisions: Vec<Option<Decision>> = images.iter().map(|i| i.decision).collect();
+        assert_eq!(
+            decisions,
+            vec![Some(Decision::Keep), Some(Decision::Reject), None]
+        );
+        let summary = Culling::summary(&images);
+        assert_eq!((summary.keep, summary.reject), (1, 1));
+
+        let lists = Culling::write_lists(&store, &images).unwrap();
+        assert_eq!(lists.len(), 1);
+        assert!(lists[0].ends_with(REJECTS_FILE));
+        assert_eq!(
+            fs::read_to_string(&lists[0]).unwrap(),
+            format!("{}\n", images[1].source)
+        );
+
+        let mut annotations = Annotations::load(&roots, None);
+        let actions = FileActions::new(&roots, TrashKind::Folder);
+        let applied = apply(&mut annotations, &actions, &mut images, "Rejects").unwrap();
+        assert_eq!(applied.len(), 1);
+        assert_eq!(images[1].relative_path, "Rejects/day/b.jpg");
+        assert!(root.path().join("Rejects/day/b.jpg").exists());
+
+        // Applying again leaves them where they are, still rejected.
+        let applied = apply(&mut annotations, &actions, &mut images, "Rejects").unwrap();
+        assert!(applied.is_empty());
+        let mut again = vec![test_file(&root_path, "Rejects/day/b.jpg")];
+        Culling::load(&store, &roots).mark(&mut again);
+        assert_eq!(again[0].decision, Some(Decision::Reject));
+    }
+}
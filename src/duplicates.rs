@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::SystemTime;
+
+use image::{DynamicImage, ImageReader};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::image_info::ImageInfo;
+use crate::sniff::MediaType;
+use crate::tags::content_hash;
+
+/// How many of the 64 bits of two perceptual hashes can differ for the images to count as the same picture.
+pub const DEFAULT_DISTANCE: u32 = 8;
+
+/// How duplicates are matched. Files with the same contents always are, the perceptual hashes also find copies that
+/// have been resized or saved again.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    /// Only files with exactly the same contents.
+    Exact,
+    /// The difference hash, from how the brightness changes across a 9x8 copy. Quick, and good at finding resized
+    /// copies.
+    Dhash,
+    /// From the lowest frequencies of a 32x32 copy, so it copes better with changes in colour and compression.
+    #[default]
+    Phash,
+}
+
+/// What's been worked out about one file. Each part is only filled in when something needs it.
+#[derive(Debug, Clone, Default)]
+pub struct FileHashes {
+    size: u64,
+    modified: Option<SystemTime>,
+    content: Option<String>,
+    perceptual: Option<Perceptual>,
+    /// Whether the file has been decoded for its perceptual hashes, so one that can't be isn't tried every time.
+    decoded: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Perceptual {
+    pub dhash: u64,
+    pub phash: u64,
+}
+
+/// The hashes of every file looked at so far, by path. Kept while the server runs and checked against the size and
+/// modified time of the file before being used.
+#[derive(Debug, Clone, Default)]
+pub struct HashCache {
+    by_path: HashMap<PathBuf, FileHashes>,
+}
+
+/// A file that may need hashing, with its hashes from last time. Copied out of the cache so the files can be looked
+/// at without holding the lock.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    path: PathBuf,
+    media_type: MediaType,
+    cached: Option<FileHashes>,
+}
+
+/// A file that needs hashing, and which hashes it needs.
+#[derive(Debug, Clone)]
+pub struct Job {
+    path: PathBuf,
+    content: bool,
+    perceptual: bool,
+    existing: FileHashes,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    /// Whether every file in the group has exactly the same contents.
+    pub exact: bool,
+    /// The largest first, as that's usually the one to keep.
+    pub files: Vec<DuplicateFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateFile {
+    #[serde(flatten)]
+    pub image: ImageInfo,
+    /// In bytes.
+    pub size: u64,
+}
+
+impl HashCache {
+    pub fn candidates(&self, images: &[ImageInfo]) -> Vec<Candidate> {
+        images
+            .iter()
+            .map(|image| Candidate {
+                path: image.path.clone(),
+                media_type: image.media_type,
+                cached: self.by_path.get(&image.path).cloned(),
+            })
+            .collect()
+    }
+
+    pub fn insert(&mut self, hashes: Vec<(PathBuf, FileHashes)>) {
+        self.by_path.extend(hashes);
+    }
+
+    fn get(&self, image: &ImageInfo) -> Option<&FileHashes> {
+        self.by_path.get(&image.path)
+    }
+}
+
+/// The files whose hashes are missing or out of date. The contents are only hashed for files that have the same size
+/// as another, nothing else can be an exact copy.
+pub fn jobs(candidates: Vec<Candidate>, method: Method) -> Vec<Job> {
+    let sizes: Vec<(u64, Option<SystemTime>)> = candidates
+        .iter()
+        .map(|candidate| match fs::metadata(&candidate.path) {
+            Ok(metadata) => (metadata.len(), metadata.modified().ok()),
+            Err(_) => (0, None),
+        })
+        .collect();
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for (size, _) in &sizes {
+        *counts.entry(*size).or_default() += 1;
+    }
+
+    candidates
+        .into_iter()
+        .zip(sizes)
+        .filter_map(|(candidate, (size, modified))| {
+            let existing = candidate
+                .cached
+                .filter(|h| h.size == size && h.modified == modified)
+                .unwrap_or(FileHashes {
+                    size,
+                    modified,
+                    ..FileHashes::default()
+                });
+            let content = counts[&size] > 1 && existing.content.is_none();
+            let perceptual =
+                method != Method::Exact && can_decode(candidate.media_type) && !existing.decoded;
+            (content || perceptual).then_some(Job {
+                path: candidate.path,
+                content,
+                perceptual,
+                existing,
+            })
+        })
+        .collect()
+}
+
+/// Works through the jobs on every core. A file that can't be read is logged and left out of the matching.
+pub fn run(jobs: Vec<Job>) -> Vec<(PathBuf, FileHashes)> {
+    if jobs.is_empty() {
+        return Vec::new();
+    }
+    info!("Hashing {} files to find duplicates", jobs.len());
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = jobs.len().div_ceil(threads);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = jobs
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(run_job).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("hashing doesn't panic"))
+            .collect()
+    })
+}
+
+fn run_job(job: &Job) -> (PathBuf, FileHashes) {
+    let mut hashes = job.existing.clone();
+    if job.content {
+        match content_hash(&job.path) {
+            Ok(hash) => hashes.content = Some(hash),
+            Err(e) => warn!("Could not read {:?} to find duplicates: {}", job.path, e),
+        }
+    }
+    if job.perceptual {
+        hashes.decoded = true;
+        match perceptual_hashes(&job.path) {
+            Ok(perceptual) => hashes.perceptual = Some(perceptual),
+            Err(e) => warn!("Could not decode {:?} to compare it: {}", job.path, e),
+        }
+    }
+    (job.path.clone(), hashes)
+}
+
+/// The formats the image crate is built to decode. Videos and svgs only ever match exactly.
+fn can_decode(media_type: MediaType) -> bool {
+    matches!(
+        media_type,
+        MediaType::Jpeg
+            | MediaType::Png
+            | MediaType::Gif
+            | MediaType::Webp
+            | MediaType::Bmp
+            | MediaType::Tiff
+    )
+}
+
+fn perceptual_hashes(path: &Path) -> Result<Perceptual, Error> {
+    // The contents decide the format rather than the extension, in case the file is misnamed.
+    let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    Ok(Perceptual {
+        dhash: dhash(&image),
+        phash: phash(&image),
+    })
+}
+
+/// One bit for each pair of neighbouring pixels in a row of a 9x8 grey copy, set when the left one is brighter.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.thumbnail_exact(9, 8).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+/// The discrete cosine transform of a 32x32 grey copy, with a bit for each of the lowest 8x8 frequencies set when it
+/// is above their median.
+pub fn phash(image: &DynamicImage) -> u64 {
+    const SIZE: usize = 32;
+    const LOW: usize = 8;
+    let small = image.thumbnail_exact(SIZE as u32, SIZE as u32).to_luma8();
+    let cosines: Vec<[f64; LOW]> = (0..SIZE)
+        .map(|x| {
+            std::array::from_fn(|u| {
+                ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * SIZE) as f64).cos()
+            })
+        })
+        .collect();
+
+    // Rows first, then columns, only working out the frequencies that are used.
+    let mut rows = [[0f64; LOW]; SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..SIZE)
+                .map(|x| small.get_pixel(x as u32, y as u32).0[0] as f64 * cosines[x][u])
+                .sum();
+        }
+    }
+    let mut frequencies = [0f64; LOW * LOW];
+    for v in 0..LOW {
+        for u in 0..LOW {
+            frequencies[v * LOW + u] = (0..SIZE).map(|y| rows[y][u] * cosines[y][v]).sum();
+        }
+    }
+
+    // The first is the average brightness, it's left out of the median so it doesn't pull it up.
+    let mut sorted = frequencies[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    frequencies
+        .iter()
+        .fold(0u64, |hash, f| (hash << 1) | u64::from(*f > median))
+}
+
+/// Groups the images that are copies of each other. Images that haven't been hashed, see [HashCache::jobs], are left
+/// out.
+pub fn find_duplicates(
+    images: &[ImageInfo],
+    cache: &HashCache,
+    method: Method,
+    distance: u32,
+) -> Vec<DuplicateGroup> {
+    let hashes: Vec<Option<&FileHashes>> = images.iter().map(|i| cache.get(i)).collect();
+    let mut groups = Groups::new(images.len());
+
+    let mut by_content: HashMap<&str, usize> = HashMap::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        if let Some(content) = hash.and_then(|h| h.content.as_deref()) {
+            let first = *by_content.entry(content).or_insert(i);
+            groups.join(first, i);
+        }
+    }
+
+    if method != Method::Exact {
+        let perceptual: Vec<(usize, u64)> = hashes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, hash)| {
+                let perceptual = (*hash)?.perceptual?;
+                Some((
+                    i,
+                    match method {
+                        Method::Dhash => perceptual.dhash,
+                        _ => perceptual.phash,
+                    },
+                ))
+            })
+            .collect();
+        for (n, (i, a)) in perceptual.iter().enumerate() {
+            for (j, b) in &perceptual[n + 1..] {
+                if (a ^ b).count_ones() <= distance {
+                    groups.join(*i, *j);
+                }
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..images.len() {
+        members.entry(groups.find(i)).or_default().push(i);
+    }
+    let mut result: Vec<DuplicateGroup> = members
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let content = |i: usize| hashes[i].and_then(|h| h.content.as_deref());
+            let exact = content(members[0]).is_some()
+                && members.iter().all(|i| content(*i) == content(members[0]));
+            let mut files: Vec<DuplicateFile> = members
+                .iter()
+                .map(|i| DuplicateFile {
+                    image: images[*i].clone(),
+                    size: hashes[*i].map_or(0, |h| h.size),
+                })
+                .collect();
+            files.sort_by(|a, b| {
+                (b.image.width * b.image.height)
+                    .cmp(&(a.image.width * a.image.height))
+                    .then(b.size.cmp(&a.size))
+                    .then(a.image.source.cmp(&b.image.source))
+            });
+            DuplicateGroup { exact, files }
+        })
+        .collect();
+    result.sort_by(|a, b| a.files[0].image.source.cmp(&b.files[0].image.source));
+    result
+}
+
+/// Union find, for joining up matches of matches.
+struct Groups {
+    parents: Vec<usize>,
+}
+
+impl Groups {
+    fn new(size: usize) -> Groups {
+        Groups {
+            parents: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[a.max(b)] = a.min(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+    use tempfile::tempdir;
+
+    use crate::duplicates::{dhash, find_duplicates, jobs, phash, run, HashCache, Method};
+    use crate::image_info::ImageInfo;
+    use crate::sniff::MediaType;
+    use crate::tree::tests::simple_image;
+
+    /// An 8x8 grid of blocks of different brightnesses, a different grid for each seed.
+    fn blocks(width: u32, height: u32, seed: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let cell = (x * 8 / width) * 8 + y * 8 / height;
+            let value = ((cell * 7919 + seed * 104729) % 251) as u8;
+            Rgb([value, value / 2, 255 - value])
+        }))
+    }
+
+    fn save(folder: &Path, name: &str, image: &DynamicImage) -> ImageInfo {
+        let path = folder.join(name);
+        image.save_with_format(&path, ImageFormat::Png).unwrap();
+        let mut info = simple_image(image.height() as u64);
+        info.width = image.width() as u64;
+        info.url = name.to_string();
+        info.source = path.to_string_lossy().to_string();
+        info.path = path;
+        info.media_type = MediaType::Png;
+        info
+    }
+
+    #[test]
+    fn perceptual_hashes() {
+        let image = blocks(64, 48, 1);
+        let smaller = image.resize_exact(32, 24, image::imageops::FilterType::Triangle);
+        let other = blocks(64, 48, 2);
+
+        assert!((phash(&image) ^ phash(&smaller)).count_ones() <= 4);
+        assert!((dhash(&image) ^ dhash(&smaller)).count_ones() <= 4);
+        assert!((phash(&image) ^ phash(&other)).count_ones() > 16);
+        assert!((dhash(&image) ^ dhash(&other)).count_ones() > 16);
+    }
+
+    #[test]
+    fn groups() {
+        let folder = tempdir().unwrap();
+        let original = save(folder.path(), "a.png", &blocks(64, 48, 1));
+        let images = vec![
+            original.clone(),
+            save(folder.path(), "b.png", &blocks(32, 24, 1)),
+            save(folder.path(), "c.png", &blocks(64, 48, 2)),
+        ];
+        let mut copy = original.clone();
+        copy.path = folder.path().join("copy.png");
+        copy.source = copy.path.to_string_lossy().to_string();
+        fs::copy(&original.path, &copy.path).unwrap();
+        let mut images = images;
+        images.push(copy);
+
+        let mut cache = HashCache::default();
+        cache.insert(run(jobs(cache.candidates(&images), Method::Exact)));
+        let exact = find_duplicates(&images, &cache, Method::Exact, 0);
+        assert_eq!(exact.len(), 1);
+        assert!(exact[0].exact);
+        assert_eq!(exact[0].files.len(), 2);
+
+        cache.insert(run(jobs(cache.candidates(&images), Method::Phash)));
+        // Everything is hashed now.
+        assert!(jobs(cache.candidates(&images), Method::Dhash).is_empty());
+        let similar = find_duplicates(&images, &cache, Method::Phash, 8);
+        assert_eq!(similar.len(), 1);
+        assert!(!similar[0].exact);
+        let names: Vec<&str> = similar[0]
+            .files
+            .iter()
+            .map(|f| f.image.url.as_str())
+            .collect();
+        // The biggest first.
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[2], "b.png");
+    }
+}
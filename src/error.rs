@@ -13,15 +13,57 @@ pub enum Error {
     #[error("an image size reading error {0:?}")]
     ImageSize(#[from] imagesize::ImageError),
 
+    #[error("an image decoding error {0:?}")]
+    Image(#[from] image::ImageError),
+
+    #[error("invalid ignore pattern: {0}")]
+    Ignore(#[from] ignore::Error),
+
+    #[error("invalid search pattern: {0}")]
+    Glob(#[from] globset::Error),
+
+    #[error("invalid filter: {0}")]
+    FilterExpr(String),
+
+    #[error("an xml parsing error {0:?}")]
+    Xml(#[from] quick_xml::Error),
+
     #[error("a mp4 processing error {0:?}")]
     Mp4(#[from] mp4::Error),
 
     #[error("an error calling ffprobe")]
     MissingFFProbe,
 
+    #[error("an error calling ffmpeg")]
+    MissingFFMpeg,
+
+    #[error("could not create a poster frame for {0}")]
+    PosterFrame(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
     #[error("invalid path: {0}")]
     InvalidPath(String),
 
+    #[error("invalid name: {0}")]
+    InvalidName(String),
+
+    #[error("invalid xmp: {0}")]
+    Xmp(String),
+
+    #[error("ratings go from -1 for rejected up to 5, not {0}")]
+    InvalidRating(String),
+
+    #[error("images can stay up for 0 to {max} seconds, not {0}", max = crate::slideshow::MAX_IMAGE_SECONDS)]
+    InvalidSeconds(f64),
+
+    #[error("not allowed: {0}")]
+    ReadOnly(String),
+
+    #[error("already exists: {0}")]
+    AlreadyExists(String),
+
     #[cfg(feature = "post")]
     #[error("could not find config path")]
     NoConfigPath,
@@ -30,9 +72,61 @@ pub enum Error {
     UnknownApiType(String),
 }
 
+impl Error {
+    /// The name of the variant, a short machine readable reason to go alongside the message.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "Io",
+            Error::Serde(_) => "Serde",
+            Error::Lock() => "Lock",
+            Error::ImageSize(_) => "ImageSize",
+            Error::Image(_) => "Image",
+            Error::Ignore(_) => "Ignore",
+            Error::Glob(_) => "Glob",
+            Error::FilterExpr(_) => "FilterExpr",
+            Error::Xml(_) => "Xml",
+            Error::Mp4(_) => "Mp4",
+            Error::MissingFFProbe => "MissingFFProbe",
+            Error::MissingFFMpeg => "MissingFFMpeg",
+            Error::PosterFrame(_) => "PosterFrame",
+            Error::NotFound(_) => "NotFound",
+            Error::InvalidPath(_) => "InvalidPath",
+            Error::InvalidName(_) => "InvalidName",
+            Error::Xmp(_) => "Xmp",
+            Error::InvalidRating(_) => "InvalidRating",
+            Error::InvalidSeconds(_) => "InvalidSeconds",
+            Error::ReadOnly(_) => "ReadOnly",
+            Error::AlreadyExists(_) => "AlreadyExists",
+            #[cfg(feature = "post")]
+            Error::NoConfigPath => "NoConfigPath",
+            #[cfg(feature = "post")]
+            Error::UnknownApiType(_) => "UnknownApiType",
+        }
+    }
+}
+
 impl actix_web::ResponseError for Error {
     fn status_code(&self) -> actix_web::http::StatusCode {
-        actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            Error::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
+            Error::Glob(_)
+            | Error::FilterExpr(_)
+            | Error::InvalidName(_)
+            | Error::InvalidRating(_)
+            | Error::InvalidSeconds(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::ReadOnly(_) => actix_web::http::StatusCode::FORBIDDEN,
+            // Such as a read-only mount, which is only found when something is written to it.
+            Error::Io(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem
+                ) =>
+            {
+                actix_web::http::StatusCode::FORBIDDEN
+            }
+            Error::AlreadyExists(_) => actix_web::http::StatusCode::CONFLICT,
+            _ => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 
     fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
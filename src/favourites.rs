@@ -0,0 +1,135 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::image_info::ImageInfo;
+use crate::store::{PerRoot, Store};
+
+pub const FAVOURITES_FILE: &str = "favourites.json";
+
+/// The favourite files under each root, by their path from the root.
+#[derive(Debug, Clone)]
+pub struct Favourites {
+    by_root: PerRoot<BTreeSet<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Favourite {
+    pub root: String,
+    pub relative_path: String,
+    pub path: String,
+}
+
+impl Default for Favourites {
+    fn default() -> Favourites {
+        Favourites {
+            by_root: PerRoot::new(FAVOURITES_FILE),
+        }
+    }
+}
+
+impl Favourites {
+    pub fn load(store: &Store, roots: &[String]) -> Favourites {
+        Favourites {
+            by_root: PerRoot::load(store, roots, FAVOURITES_FILE),
+        }
+    }
+
+    pub fn contains(&self, image: &ImageInfo) -> bool {
+        self.by_root
+            .get(&image.root)
+            .is_some_and(|f| f.contains(&image.relative_path))
+    }
+
+    /// Sets the `favourite` field of each image, after a scan.
+    pub fn mark(&self, images: &mut [ImageInfo]) {
+        for image in images.iter_mut() {
+            image.favourite = self.contains(image);
+        }
+    }
+
+    /// Adds or removes a favourite and saves the root's list straight away.
+    pub fn set(&mut self, store: &Store, image: &ImageInfo, favourite: bool) -> Result<(), Error> {
+        self.by_root.update(store, &image.root, |favourites| {
+            if favourite {
+                favourites.insert(image.relative_path.clone());
+            } else {
+                favourites.remove(&image.relative_path);
+            }
+            Ok(())
+        })
+    }
+
+    /// Keeps a file that has been moved or renamed a favourite.
+    pub fn moved(&mut self, store: &Store, image: &ImageInfo, old_path: &str) -> Result<(), Error> {
+        self.by_root
+            .rename_key(store, &image.root, old_path, &image.relative_path)
+    }
+
+    /// Every favourite, including any whose files can't currently be found.
+    pub fn list(&self) -> Vec<Favourite> {
+        let mut list: Vec<Favourite> = self
+            .by_root
+            .iter()
+            .flat_map(|(root, favourites)| {
+                favourites.iter().map(move |relative_path| Favourite {
+                    root: root.clone(),
+                    relative_path: relative_path.clone(),
+                    path: full_path(root, relative_path),
+                })
+            })
+            .collect();
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+        list
+    }
+}
+
+fn full_path(root: &str, relative_path: &str) -> String {
+    let root = Path::new(root);
+    if root.is_file() {
+        root.to_string_lossy().to_string()
+    } else {
+        root.join(relative_path).to_string_lossy().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::favourites::Favourites;
+    use crate::store::Store;
+    use crate::tree::tests::test_image;
+
+    #[test]
+    fn persisted() {
+        let root = tempdir().unwrap();
+        let roots = vec![root.path().to_string_lossy().to_string()];
+        let store = Store::new(&roots, None);
+
+        let image = test_image(&roots[0], "holiday/beach.png");
+        let other = test_image(&roots[0], "snow.png");
+
+        let mut favourites = Favourites::load(&store, &roots);
+        favourites.set(&store, &image, true).unwrap();
+        favourites.set(&store, &other, true).unwrap();
+        favourites.set(&store, &other, false).unwrap();
+
+        // A fresh load, like after a restart.
+        let favourites = Favourites::load(&store, &roots);
+        let mut images = vec![image, other];
+        favourites.mark(&mut images);
+        assert!(images[0].favourite);
+        assert!(!images[1].favourite);
+
+        let list = favourites.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].relative_path, "holiday/beach.png");
+        assert_eq!(
+            list[0].path,
+            root.path().join("holiday/beach.png").to_string_lossy()
+        );
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use chrono::Local;
+use clap::ValueEnum;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::image_info::{image_url, ImageInfo};
+use crate::sniff::MediaType;
+use crate::xmp::sidecar_moves;
+
+/// The folder made in a root for files moved to the trash, hidden so it's never scanned.
+pub const TRASH_FOLDER: &str = ".trash";
+
+/// Where files go when they are deleted from the page.
+#[derive(ValueEnum, Serialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashKind {
+    /// A `.trash` folder in the root, keeping the same sub folders.
+    #[default]
+    Folder,
+    /// The desktop's trash, following the freedesktop.org spec, so files can be restored from the file manager.
+    Freedesktop,
+}
+
+impl std::fmt::Display for TrashKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+/// Deleting, moving and renaming files, only there when the server is started with `--allow-write`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileActions {
+    pub trash: TrashKind,
+    /// The roots files can't be changed under and why, such as a read-only mount.
+    pub read_only: BTreeMap<String, String>,
+}
+
+impl FileActions {
+    /// Checks the permissions of each root. Nothing is written to find out, so a read-only mount is only found at the
+    /// first change.
+    pub fn new(roots: &[String], trash: TrashKind) -> FileActions {
+        let read_only = roots
+            .iter()
+            .filter_map(|root| {
+                let reason = if Path::new(root).is_file() {
+                    "files given on their own can't be changed".to_string()
+                } else {
+                    read_only_reason(Path::new(root))?
+                };
+                warn!("{root} is read-only, {reason}");
+                Some((root.clone(), reason))
+            })
+            .collect();
+        FileActions { trash, read_only }
+    }
+
+    fn check(&self, image: &ImageInfo) -> Result<(), Error> {
+        match self.read_only.get(&image.root) {
+            Some(reason) => Err(Error::ReadOnly(format!(
+                "{} is read-only, {}",
+                image.root, reason
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns where the file went.
+    pub fn trash(&self, image: &ImageInfo) -> Result<PathBuf, Error> {
+        self.check(image)?;
+        let target = match self.trash {
+            TrashKind::Folder => {
+                let target = free_name(
+                    &Path::new(&image.root)
+                        .join(TRASH_FOLDER)
+                        .join(&image.relative_path),
+                );
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                move_with_sidecars(&image.path, &target)?;
+                target
+            }
+            TrashKind::Freedesktop => {
+                // Each sidecar gets its own info file so the file manager can restore it too.
+                let sidecars = sidecar_moves(&image.path, &image.path);
+                let target = freedesktop_trash(&image.path)?;
+                for (sidecar, _) in sidecars {
+                    if let Err(e) = freedesktop_trash(&sidecar) {
+                        warn!("Could not move {:?} to the trash: {}", sidecar, e);
+                    }
+                }
+                target
+            }
+        };
+        info!("Moved {} to {:?}", image.source, target);
+        Ok(target)
+    }
+
+    /// Moves the file into a folder under its root, made if it's not there. Returns the old path from the root.
+    pub fn move_to(&self, image: &mut ImageInfo, folder: &str) -> Result<String, Error> {
+        let name = image
+            .relative_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&image.relative_path)
+            .to_string();
+        let folder = check_folder(folder)?;
+        let relative_path = if folder.is_empty() {
+            name
+        } else {
+            format!("{folder}/{name}")
+        };
+        self.relocate(image, relative_path)
+    }
+
+    /// Renames the file, keeping it in the same folder. A name without an extension keeps the one the file had.
+    /// Returns the old path from the root.
+    pub fn rename(&self, image: &mut ImageInfo, name: &str) -> Result<String, Error> {
+        let name = check_file_name(name)?;
+        let name = match Path::new(name).extension() {
+            None => match Path::new(&image.relative_path).extension() {
+                Some(extension) => format!("{name}.{}", extension.to_string_lossy()),
+                None => name.to_string(),
+            },
+            Some(extension)
+                if MediaType::from_extension(&extension.to_string_lossy()).is_some() =>
+            {
+                name.to_string()
+            }
+            // It would be skipped the next time the folder is scanned.
+            Some(_) => {
+                return Err(Error::InvalidName(format!(
+                    "{name:?} has to end in the extension of a picture or video, like .jpg"
+                )))
+            }
+        };
+        let relative_path = match image.relative_path.rsplit_once('/') {
+            Some((folder, _)) => format!("{folder}/{name}"),
+            None => name.to_string(),
+        };
+        self.relocate(image, relative_path)
+    }
+
+    fn relocate(&self, image: &mut ImageInfo, relative_path: String) -> Result<String, Error> {
+        self.check(image)?;
+        if relative_path == image.relative_path {
+            return Ok(relative_path);
+        }
+        let target = Path::new(&image.root).join(&relative_path);
+        if target.exists() {
+            return Err(Error::AlreadyExists(relative_path));
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Checked again by the move itself, something could have been put there since.
+        move_with_sidecars(&image.path, &target).map_err(|e| match e {
+            Error::Io(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Error::AlreadyExists(relative_path.clone())
+            }
+            e => e,
+        })?;
+        info!("Moved {} to {:?}", image.source, target);
+
+        // The same as a fresh scan would give it, so it's the same after a restart.
+        image.url = image_url(&image.root, &target, image.media_type);
+        image.source = target.to_string_lossy().to_string();
+        image.path = target;
+        Ok(std::mem::replace(&mut image.relative_path, relative_path))
+    }
+}
+
+fn read_only_reason(folder: &Path) -> Option<String> {
+    match fs::metadata(folder) {
+        Ok(metadata) if metadata.permissions().readonly() => {
+            Some("its permissions don't allow changes".to_string())
+        }
+        Ok(_) => None,
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+/// A folder under the root, with `/` between the names. Nothing that could go outside the root or into a hidden folder.
+fn check_folder(folder: &str) -> Result<String, Error> {
+    let parts: Vec<&str> = folder
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty())
+        .collect();
+    for part in &parts {
+        check_file_name(part)?;
+    }
+    Ok(parts.join("/"))
+}
+
+fn check_file_name(name: &str) -> Result<&str, Error> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None)
+            if !name.starts_with('.') && !name.contains(['/', '\\']) =>
+        {
+            Ok(name)
+        }
+        _ => Err(Error::InvalidName(format!(
+            "{name:?} has to be a plain name, not hidden or with a path in it"
+        ))),
+    }
+}
+
+/// Moves a file, copying it if it's going to another drive, then takes its xmp sidecars along.
+fn move_with_sidecars(from: &Path, to: &Path) -> Result<(), Error> {
+    let sidecars = sidecar_moves(from, to);
+    move_file(from, to)?;
+    for (sidecar_from, sidecar_to) in sidecars {
+        if let Err(e) = move_file(&sidecar_from, &sidecar_to) {
+            warn!(
+                "Could not move {:?} along with {:?}: {}",
+                sidecar_from, from, e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Never replaces a file already at `to`, unlike a rename, failing with `AlreadyExists` instead.
+fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    match fs::hard_link(from, to) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e.into()),
+        // Another device, or a file system without hard links.
+        Err(_) => {
+            if let Err(e) = copy_new(from, to) {
+                let _ = fs::remove_file(to);
+                return Err(e);
+            }
+        }
+    }
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Copies to a file that must not exist yet, keeping the modified time the date may have come from.
+fn copy_new(from: &Path, to: &Path) -> Result<(), Error> {
+    let mut source = File::open(from)?;
+    let metadata = source.metadata()?;
+    let mut target = File::create_new(to)?;
+    io::copy(&mut source, &mut target)?;
+    target.set_permissions(metadata.permissions())?;
+    target.set_modified(metadata.modified()?)?;
+    Ok(())
+}
+
+/// The path, or if there's already something there the same name with a number on the end, `beach.2.jpg`.
+fn free_name(path: &Path) -> PathBuf {
+    let mut candidate = path.to_path_buf();
+    let mut n = 2;
+    while candidate.exists() {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        candidate = match path.extension() {
+            Some(extension) => {
+                path.with_file_name(format!("{stem}.{n}.{}", extension.to_string_lossy()))
+            }
+            None => path.with_file_name(format!("{stem}.{n}")),
+        };
+        n += 1;
+    }
+    candidate
+}
+
+/// The home trash, `$XDG_DATA_HOME/Trash`. Each file gets a `.trashinfo` saying where it came from so it can be
+/// restored.
+fn freedesktop_trash(path: &Path) -> Result<PathBuf, Error> {
+    let data_home =
+        match env::var_os("XDG_DATA_HOME") {
+            Some(data_home) if !data_home.is_empty() => PathBuf::from(data_home),
+            _ => PathBuf::from(env::var_os("HOME").ok_or_else(|| {
+                Error::NotFound("HOME isn't set, so there's no trash".to_string())
+            })?)
+            .join(".local/share"),
+        };
+    let trash = data_home.join("Trash");
+    fs::create_dir_all(trash.join("files"))?;
+    fs::create_dir_all(trash.join("info"))?;
+
+    // The info file is made first with create_new, which claims the name even if something else is trashing too.
+    let original = fs::canonicalize(path)?;
+    let mut target = trash
+        .join("files")
+        .join(path.file_name().unwrap_or_default());
+    let (info_path, mut info_file) = loop {
+        target = free_name(&target);
+        let info_path = trash.join("info").join(format!(
+            "{}.trashinfo",
+            target.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(file) => break (info_path, file),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                target = target.with_file_name(format!(
+                    "{}.trashed",
+                    target.file_name().unwrap_or_default().to_string_lossy()
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    write!(
+        info_file,
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(&original.to_string_lossy()),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    )?;
+
+    if let Err(e) = move_file(path, &target) {
+        fs::remove_file(info_path)?;
+        return Err(e);
+    }
+    Ok(target)
+}
+
+/// Paths in `.trashinfo` files are escaped like urls, apart from the `/`s.
+fn percent_encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::error::Error;
+    use crate::file_actions::{
+        copy_new, move_file, percent_encode, FileActions, TrashKind, TRASH_FOLDER,
+    };
+    use crate::image_info::image_url;
+    use crate::tree::tests::test_file;
+
+    #[test]
+    fn move_and_rename() {
+        let root = tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let actions = FileActions::new(std::slice::from_ref(&root_path), TrashKind::Folder);
+        assert!(actions.read_only.is_empty());
+
+        let mut beach = test_file(&root_path, "beach.jpg");
+        fs::write(root.path().join("beach.jpg.xmp"), "").unwrap();
+
+        let old = actions.move_to(&mut beach, "2024/summer").unwrap();
+        assert_eq!(old, "beach.jpg");
+        assert_eq!(beach.relative_path, "2024/summer/beach.jpg");
+        assert!(root.path().join("2024/summer/beach.jpg").exists());
+        assert!(root.path().join("2024/summer/beach.jpg.xmp").exists());
+        assert!(!root.path().join("beach.jpg").exists());
+
+        actions.rename(&mut beach, "sand.jpg").unwrap();
+        assert_eq!(beach.relative_path, "2024/summer/sand.jpg");
+        assert_eq!(beach.path, root.path().join("2024/summer/sand.jpg"));
+        assert_eq!(
+            beach.url,
+            image_url(&root_path, &beach.path, beach.media_type)
+        );
+
+        // Nothing outside the root, hidden or in the way.
+        let mut snow = test_file(&root_path, "snow.jpg");
+        for folder in ["..", "../elsewhere", ".hidden", "a/../.."] {
+            assert!(actions.move_to(&mut snow, folder).is_err(), "{folder}");
+        }
+        for name in ["", "a/b.jpg", "..", ".hidden.jpg"] {
+            assert!(actions.rename(&mut snow, name).is_err(), "{name}");
+        }
+        assert!(actions.rename(&mut snow, "snow.jpg").is_ok());
+        test_file(&root_path, "taken.jpg");
+        assert!(actions.rename(&mut snow, "taken.jpg").is_err());
+        assert!(root.path().join("snow.jpg").exists());
+    }
+
+    #[test]
+    fn rename_extensions() {
+        let root = tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let actions = FileActions::new(std::slice::from_ref(&root_path), TrashKind::Folder);
+        let mut beach = test_file(&root_path, "beach.jpg");
+
+        actions.rename(&mut beach, "sand").unwrap();
+        assert_eq!(beach.relative_path, "sand.jpg");
+        for name in ["sand.txt", "sand.2024"] {
+            assert!(
+                matches!(actions.rename(&mut beach, name), Err(Error::InvalidName(_))),
+                "{name}"
+            );
+        }
+        actions.rename(&mut beach, "dunes.JPEG").unwrap();
+        assert_eq!(beach.relative_path, "dunes.JPEG");
+        assert!(root.path().join("dunes.JPEG").exists());
+    }
+
+    #[test]
+    fn moves_never_replace() {
+        let root = tempdir().unwrap();
+        let from = root.path().join("from.jpg");
+        let to = root.path().join("to.jpg");
+        fs::write(&from, "from").unwrap();
+        fs::write(&to, "to").unwrap();
+
+        assert!(matches!(
+            move_file(&from, &to),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists
+        ));
+        assert!(copy_new(&from, &to).is_err());
+        assert_eq!(fs::read_to_string(&from).unwrap(), "from");
+        assert_eq!(fs::read_to_string(&to).unwrap(), "to");
+
+        let copied = root.path().join("copied.jpg");
+        copy_new(&from, &copied).unwrap();
+        assert_eq!(
+            fs::metadata(&copied).unwrap().modified().unwrap(),
+            fs::metadata(&from).unwrap().modified().unwrap()
+        );
+        fs::remove_file(&to).unwrap();
+        move_file(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "from");
+    }
+
+    #[test]
+    fn trash_folder() {
+        let root = tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let actions = FileActions::new(std::slice::from_ref(&root_path), TrashKind::Folder);
+
+        actions
+            .trash(&test_file(&root_path, "sub/beach.jpg"))
+            .unwrap();
+        let again = actions
+            .trash(&test_file(&root_path, "sub/beach.jpg"))
+            .unwrap();
+        assert!(root
+            .path()
+            .join(TRASH_FOLDER)
+            .join("sub/beach.jpg")
+            .exists());
+        assert_eq!(
+            again,
+            root.path().join(TRASH_FOLDER).join("sub/beach.2.jpg")
+        );
+        assert!(!root.path().join("sub/beach.jpg").exists());
+    }
+
+    #[test]
+    fn read_only() {
+        let root = tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let single = test_file(&root_path, "single.jpg");
+        let single_root = single.path.to_string_lossy().to_string();
+        let actions = FileActions::new(std::slice::from_ref(&single_root), TrashKind::Folder);
+
+        let mut single = single;
+        single.root = single_root;
+        assert!(actions.trash(&single).is_err());
+        assert!(actions.rename(&mut single, "other.jpg").is_err());
+        assert!(single.path.exists());
+
+        let mut permissions = fs::metadata(root.path()).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(root.path(), permissions.clone()).unwrap();
+        let actions = FileActions::new(std::slice::from_ref(&root_path), TrashKind::Folder);
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(root.path(), permissions).unwrap();
+        assert!(actions.read_only.contains_key(&root_path));
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn trash_info_paths() {
+        assert_eq!(
+            percent_encode("/home/me/Beach day/100%.jpg"),
+            "/home/me/Beach%20day/100%25.jpg"
+        );
+    }
+}
@@ -0,0 +1,776 @@
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use chrono::{Datelike, Local, NaiveDate, TimeZone};
+use clap::Args;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Deserializer};
+
+use crate::culling::Decision;
+use crate::error::Error;
+use crate::image_info::ImageInfo;
+use crate::sniff::MediaType;
+
+/// A filter written as space separated terms, all of which have to match. For example
+/// `type:video min-width:1920 orientation:portrait date:2023..2024 name:*beach*`.
+///
+/// - `key:a,b` matches if any of the values do.
+/// - `-key:value` matches everything the term doesn't.
+/// - A word without a key has to appear somewhere in the path, ignoring case.
+///
+/// The keys are `type` (`video`, `image`, `animated`, `static` or a format such as `gif`), `min-width`, `max-width`,
+/// `min-height`, `max-height`, `orientation` (`portrait`, `landscape` or `square`), `date` (`2023`, `2023-05`,
+/// `2023-05-01` or a range of those like `2023..2024`, either end can be left off), `aspect` (`16:9`, `1.5` or a range
+/// like `4:3..16:9`), `name` and `path` which take globs over the file name and the path from the root, `is`
+/// (`favourite`), `tag`, `album`, `rating` (`-1` for rejected, `0` for unrated up to `5`, or a range like `3..`),
+/// `label` (the colour label from xmp) and `duration` or `length` (seconds, or a range like `10..60`).
+#[derive(Debug, Clone, Default)]
+pub struct FilterExpr {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone)]
+struct Term {
+    negated: bool,
+    condition: Condition,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Type(Vec<TypeFilter>),
+    MinWidth(u64),
+    MaxWidth(u64),
+    MinHeight(u64),
+    MaxHeight(u64),
+    Orientation(Vec<Orientation>),
+    Aspect(AspectRange),
+    /// From the start up to but not including the end.
+    Date(Option<SystemTime>, Option<SystemTime>),
+    Name(Vec<GlobMatcher>),
+    Path(Vec<GlobMatcher>),
+    Text(String),
+    Is(Vec<IsFilter>),
+    /// Lower case, tags and albums are matched ignoring case.
+    Tag(Vec<String>),
+    Album(Vec<String>),
+    /// Inclusive ranges, unrated files count as 0.
+    Rating(Vec<(i8, i8)>),
+    Label(Vec<String>),
+    /// Inclusive ranges in milliseconds, still images count as 0.
+    Duration(Vec<(u64, u64)>),
+}
+
+/// Things known about a file from what has been marked in the page rather than from the file itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IsFilter {
+    Favourite,
+    /// Culling decisions, so rejected here is not the same as a -1 rating.
+    Kept,
+    Rejected,
+    Undecided,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TypeFilter {
+    Video,
+    Image,
+    Animated,
+    Static,
+    Format(MediaType),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    Square,
+}
+
+impl Orientation {
+    /// Files whose size couldn't be read don't have an orientation.
+    pub fn of(image: &ImageInfo) -> Option<Orientation> {
+        if image.width == 0 || image.height == 0 {
+            None
+        } else if image.height > image.width {
+            Some(Orientation::Portrait)
+        } else if image.width > image.height {
+            Some(Orientation::Landscape)
+        } else {
+            Some(Orientation::Square)
+        }
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "portrait" => Ok(Orientation::Portrait),
+            "landscape" => Ok(Orientation::Landscape),
+            "square" => Ok(Orientation::Square),
+            _ => Err(Error::FilterExpr(format!("unknown orientation {s}"))),
+        }
+    }
+}
+
+/// A range of width divided by height, either end can be open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AspectRange {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl AspectRange {
+    /// How far off a single ratio can be and still count, so 1920x1080 and 1366x768 are both 16:9.
+    const TOLERANCE: f64 = 0.01;
+
+    fn contains(&self, image: &ImageInfo) -> bool {
+        if image.width == 0 || image.height == 0 {
+            return false;
+        }
+        let ratio = image.width as f64 / image.height as f64;
+        self.min.is_none_or(|min| ratio >= min) && self.max.is_none_or(|max| ratio <= max)
+    }
+}
+
+impl FromStr for AspectRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once("..") {
+            Some((min, max)) => {
+                let min = (!min.is_empty()).then(|| parse_ratio(min)).transpose()?;
+                let max = (!max.is_empty()).then(|| parse_ratio(max)).transpose()?;
+                // Portrait ranges are easy to write the wrong way round, 9:16..9:21 gets narrower.
+                match (min, max) {
+                    (Some(a), Some(b)) if a > b => Ok(AspectRange {
+                        min: Some(b),
+                        max: Some(a),
+                    }),
+                    _ => Ok(AspectRange { min, max }),
+                }
+            }
+            None => {
+                let ratio = parse_ratio(s)?;
+                Ok(AspectRange {
+                    min: Some(ratio * (1.0 - Self::TOLERANCE)),
+                    max: Some(ratio * (1.0 + Self::TOLERANCE)),
+                })
+            }
+        }
+    }
+}
+
+/// `16:9`, `16/9` or `1.78`.
+fn parse_ratio(text: &str) -> Result<f64, Error> {
+    let invalid = || Error::FilterExpr(format!("invalid aspect ratio {text}"));
+    let ratio = match text.split_once([':', '/']) {
+        Some((width, height)) => {
+            let width: f64 = width.parse().map_err(|_| invalid())?;
+            let height: f64 = height.parse().map_err(|_| invalid())?;
+            width / height
+        }
+        None => text.parse().map_err(|_| invalid())?,
+    };
+
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(invalid())
+    }
+}
+
+/// The size and shape filters, which can be given as their own command line arguments and query parameters as well as
+/// in a filter expression.
+#[derive(Args, Deserialize, Debug, Clone, Default)]
+pub struct ShapeFilter {
+    #[arg(long, help = "only show portrait, landscape or square files")]
+    #[serde(default, deserialize_with = "from_str_option")]
+    pub orientation: Option<Orientation>,
+
+    #[arg(long, help = "only show files at least this many pixels wide")]
+    #[serde(default, deserialize_with = "from_str_option")]
+    pub min_width: Option<u64>,
+
+    #[arg(long, help = "only show files at most this many pixels wide")]
+    #[serde(default, deserialize_with = "from_str_option")]
+    pub max_width: Option<u64>,
+
+    #[arg(long, help = "only show files at least this many pixels high")]
+    #[serde(default, deserialize_with = "from_str_option")]
+    pub min_height: Option<u64>,
+
+    #[arg(long, help = "only show files at most this many pixels high")]
+    #[serde(default, deserialize_with = "from_str_option")]
+    pub max_height: Option<u64>,
+
+    #[arg(
+        long,
+        help = "only show files with this aspect ratio, E.G. 16:9, or in a range like 4:3..16:9"
+    )]
+    #[serde(default, deserialize_with = "from_str_option")]
+    pub aspect: Option<AspectRange>,
+}
+
+impl ShapeFilter {
+    pub fn to_expr(&self) -> FilterExpr {
+        let conditions = [
+            self.orientation.map(|o| Condition::Orientation(vec![o])),
+            self.min_width.map(Condition::MinWidth),
+            self.max_width.map(Condition::MaxWidth),
+            self.min_height.map(Condition::MinHeight),
+            self.max_height.map(Condition::MaxHeight),
+            self.aspect.map(Condition::Aspect),
+        ];
+
+        FilterExpr {
+            terms: conditions
+                .into_iter()
+                .flatten()
+                .map(|condition| Term {
+                    negated: false,
+                    condition,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Query parameters always arrive as text, even inside a flattened struct, so they are parsed the same way as the
+/// command line arguments.
+pub(crate) fn from_str_option<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let text: Option<String> = Option::deserialize(deserializer)?;
+    text.filter(|t| !t.is_empty())
+        .map(|t| t.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+impl FilterExpr {
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// A filter that only matches what both of these do.
+    pub fn and(mut self, other: FilterExpr) -> FilterExpr {
+        self.terms.extend(other.terms);
+        self
+    }
+
+    pub fn matches(&self, image: &ImageInfo) -> bool {
+        self.terms
+            .iter()
+            .all(|term| term.condition.matches(image) != term.negated)
+    }
+}
+
+impl FromStr for FilterExpr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let terms = s
+            .split_whitespace()
+            .map(parse_term)
+            .collect::<Result<Vec<Term>, Error>>()?;
+        Ok(FilterExpr { terms })
+    }
+}
+
+fn parse_term(text: &str) -> Result<Term, Error> {
+    let (negated, text) = match text.strip_prefix('-') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, text),
+    };
+
+    let Some((key, value)) = text.split_once(':') else {
+        return Ok(Term {
+            negated,
+            condition: Condition::Text(text.to_lowercase()),
+        });
+    };
+    if value.is_empty() {
+        return Err(Error::FilterExpr(format!("{key} needs a value")));
+    }
+    let values: Vec<&str> = value.split(',').collect();
+
+    let condition = match key.to_lowercase().as_str() {
+        "type" => Condition::Type(
+            values
+                .iter()
+                .map(|v| parse_type(v))
+                .collect::<Result<_, _>>()?,
+        ),
+        "min-width" => Condition::MinWidth(parse_number(key, value)?),
+        "max-width" => Condition::MaxWidth(parse_number(key, value)?),
+        "min-height" => Condition::MinHeight(parse_number(key, value)?),
+        "max-height" => Condition::MaxHeight(parse_number(key, value)?),
+        "orientation" => {
+            Condition::Orientation(values.iter().map(|v| v.parse()).collect::<Result<_, _>>()?)
+        }
+        "aspect" => Condition::Aspect(value.parse()?),
+        "date" => {
+            let (from, to) = parse_date_range(value)?;
+            Condition::Date(from, to)
+        }
+        "name" => Condition::Name(values.iter().map(|v| glob(v)).collect::<Result<_, _>>()?),
+        "path" => Condition::Path(values.iter().map(|v| glob(v)).collect::<Result<_, _>>()?),
+        "tag" => Condition::Tag(values.iter().map(|v| v.to_lowercase()).collect()),
+        "album" => Condition::Album(values.iter().map(|v| v.to_lowercase()).collect()),
+        "rating" => Condition::Rating(
+            values
+                .iter()
+                .map(|v| parse_rating_range(v))
+                .collect::<Result<_, _>>()?,
+        ),
+        "label" => Condition::Label(values.iter().map(|v| v.to_lowercase()).collect()),
+        "duration" | "length" => Condition::Duration(
+            values
+                .iter()
+                .map(|v| parse_duration_range(v))
+                .collect::<Result<_, _>>()?,
+        ),
+        "is" => Condition::Is(
+            values
+                .iter()
+                .map(|v| parse_is(v))
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => return Err(Error::FilterExpr(format!("unknown filter {key}"))),
+    };
+
+    Ok(Term { negated, condition })
+}
+
+fn parse_type(value: &str) -> Result<TypeFilter, Error> {
+    match value.to_lowercase().as_str() {
+        "video" | "videos" => Ok(TypeFilter::Video),
+        "image" | "images" => Ok(TypeFilter::Image),
+        "animated" => Ok(TypeFilter::Animated),
+        "static" => Ok(TypeFilter::Static),
+        other => MediaType::from_extension(other)
+            .map(TypeFilter::Format)
+            .ok_or_else(|| Error::FilterExpr(format!("unknown type {value}"))),
+    }
+}
+
+fn parse_is(value: &str) -> Result<IsFilter, Error> {
+    match value.to_lowercase().as_str() {
+        "favourite" | "favorite" | "fav" => Ok(IsFilter::Favourite),
+        "kept" => Ok(IsFilter::Kept),
+        "rejected" => Ok(IsFilter::Rejected),
+        "undecided" => Ok(IsFilter::Undecided),
+        _ => Err(Error::FilterExpr(format!("unknown is:{value}"))),
+    }
+}
+
+/// `3`, or a range like `3..5`, `3..` or `..1`, all inclusive.
+fn parse_rating_range(value: &str) -> Result<(i8, i8), Error> {
+    let rating = |text: &str, default: i8| -> Result<i8, Error> {
+        if text.is_empty() {
+            return Ok(default);
+        }
+        match text.parse::<i8>() {
+            Ok(rating) if (-1..=5).contains(&rating) => Ok(rating),
+            _ => Err(Error::FilterExpr(format!(
+                "ratings go from -1 up to 5, not {text}"
+            ))),
+        }
+    };
+    match value.split_once("..") {
+        Some((from, to)) => Ok((rating(from, -1)?, rating(to, 5)?)),
+        None => {
+            let exact = rating(value, 0)?;
+            Ok((exact, exact))
+        }
+    }
+}
+
+/// Seconds, `10` meaning from 10 up to 11, or a range like `10..60`, `1.5..` or `..30`, all inclusive. Returned in
+/// milliseconds.
+fn parse_duration_range(value: &str) -> Result<(u64, u64), Error> {
+    let millis = |text: &str| -> Result<u64, Error> {
+        match text.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => {
+                Ok((seconds * 1000.0).round() as u64)
+            }
+            _ => Err(Error::FilterExpr(format!(
+                "durations are a number of seconds, not {text}"
+            ))),
+        }
+    };
+    match value.split_once("..") {
+        Some((from, to)) => Ok((
+            if from.is_empty() { 0 } else { millis(from)? },
+            if to.is_empty() { u64::MAX } else { millis(to)? },
+        )),
+        None => {
+            let from = millis(value)?;
+            Ok((from, from.saturating_add(999)))
+        }
+    }
+}
+
+fn parse_number(key: &str, value: &str) -> Result<u64, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::FilterExpr(format!("{key} needs a whole number, not {value}")))
+}
+
+fn glob(pattern: &str) -> Result<GlobMatcher, Error> {
+    Ok(GlobBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()?
+        .compile_matcher())
+}
+
+/// `2023`, `2023-05`, `2023-05-01` or two of those either side of `..`. The end date is included, so `2023..2024`
+/// runs to the end of 2024.
+fn parse_date_range(value: &str) -> Result<(Option<SystemTime>, Option<SystemTime>), Error> {
+    match value.split_once("..") {
+        Some((from, to)) => {
+            let from = if from.is_empty() {
+                None
+            } else {
+                Some(parse_period(from)?.0)
+            };
+            let to = if to.is_empty() {
+                None
+            } else {
+                Some(parse_period(to)?.1)
+            };
+            Ok((from, to))
+        }
+        None => {
+            let (from, to) = parse_period(value)?;
+            Ok((Some(from), Some(to)))
+        }
+    }
+}
+
+/// The start of a year, month or day, and the start of the one after it, in local time.
+fn parse_period(text: &str) -> Result<(SystemTime, SystemTime), Error> {
+    let invalid = || Error::FilterExpr(format!("invalid date {text}"));
+    let parts = text
+        .split('-')
+        .map(|p| p.parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<u32>, Error>>()?;
+
+    let (start, end) = match parts[..] {
+        [year] => {
+            let start = NaiveDate::from_ymd_opt(year as i32, 1, 1).ok_or_else(invalid)?;
+            (start, start.with_year(year as i32 + 1))
+        }
+        [year, month] => {
+            let start = NaiveDate::from_ymd_opt(year as i32, month, 1).ok_or_else(invalid)?;
+            (start, start.checked_add_months(chrono::Months::new(1)))
+        }
+        [year, month, day] => {
+            let start = NaiveDate::from_ymd_opt(year as i32, month, day).ok_or_else(invalid)?;
+            (start, start.succ_opt())
+        }
+        _ => return Err(invalid()),
+    };
+
+    Ok((
+        local_midnight(start)?,
+        local_midnight(end.ok_or_else(invalid)?)?,
+    ))
+}
+
+fn local_midnight(date: NaiveDate) -> Result<SystemTime, Error> {
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .map(SystemTime::from)
+        .ok_or_else(|| Error::FilterExpr(format!("no local midnight on {date}")))
+}
+
+impl Condition {
+    fn matches(&self, image: &ImageInfo) -> bool {
+        match self {
+            Condition::Type(types) => types.iter().any(|t| match t {
+                TypeFilter::Video => image.is_video,
+                TypeFilter::Image => !image.is_video,
+                TypeFilter::Animated => image.animated,
+                TypeFilter::Static => !image.is_video && !image.animated,
+                TypeFilter::Format(media_type) => image.media_type == *media_type,
+            }),
+            Condition::MinWidth(width) => image.width >= *width,
+            Condition::MaxWidth(width) => image.width <= *width,
+            Condition::MinHeight(height) => image.height >= *height,
+            Condition::MaxHeight(height) => image.height <= *height,
+            Condition::Orientation(orientations) => {
+                Orientation::of(image).is_some_and(|o| orientations.contains(&o))
+            }
+            Condition::Aspect(range) => range.contains(image),
+            Condition::Date(from, to) => {
+                from.is_none_or(|from| image.date >= from) && to.is_none_or(|to| image.date < to)
+            }
+            Condition::Name(globs) => {
+                let name = image
+                    .relative_path
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or(&image.relative_path);
+                globs.iter().any(|g| g.is_match(name))
+            }
+            Condition::Path(globs) => globs.iter().any(|g| g.is_match(&image.relative_path)),
+            Condition::Text(text) => image.relative_path.to_lowercase().contains(text),
+            Condition::Is(filters) => filters.iter().any(|f| match f {
+                IsFilter::Favourite => image.favourite,
+                IsFilter::Kept => image.decision == Some(Decision::Keep),
+                IsFilter::Rejected => image.decision == Some(Decision::Reject),
+                IsFilter::Undecided => image.decision.is_none(),
+            }),
+            Condition::Tag(tags) => has_any(&image.tags, tags),
+            Condition::Album(albums) => has_any(&image.albums, albums),
+            Condition::Rating(ranges) => {
+                let rating = image.rating.unwrap_or(0);
+                ranges
+                    .iter()
+                    .any(|(from, to)| (*from..=*to).contains(&rating))
+            }
+            Condition::Label(labels) => image
+                .label
+                .as_ref()
+                .is_some_and(|l| labels.contains(&l.to_lowercase())),
+            Condition::Duration(ranges) => ranges
+                .iter()
+                .any(|(from, to)| (*from..=*to).contains(&image.duration_ms)),
+        }
+    }
+}
+
+fn has_any(labels: &[String], wanted: &[String]) -> bool {
+    labels.iter().any(|l| wanted.contains(&l.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use crate::culling::Decision;
+    use crate::filter_expr::{FilterExpr, Orientation, ShapeFilter};
+    use crate::image_info::ImageInfo;
+    use crate::sniff::MediaType;
+    use crate::tree::tests::simple_image;
+
+    fn image(relative_path: &str, width: u64, height: u64, year: i32, month: u32) -> ImageInfo {
+        let mut image = simple_image(height);
+        image.relative_path = relative_path.to_string();
+        image.width = width;
+        image.date = Local
+            .with_ymd_and_hms(year, month, 15, 12, 0, 0)
+            .unwrap()
+            .into();
+        image
+    }
+
+    fn matches(expr: &str, image: &ImageInfo) -> bool {
+        expr.parse::<FilterExpr>().unwrap().matches(image)
+    }
+
+    #[test]
+    fn parse_errors() {
+        for bad in [
+            "colour:red",
+            "type:",
+            "type:sculpture",
+            "min-width:wide",
+            "orientation:diagonal",
+            "date:2023-13",
+            "date:last-year",
+            "date:2023-01-01-01",
+            "name:[",
+            "is:lost",
+            "rating:6",
+            "rating:good",
+            "duration:long",
+            "length:-5",
+            "duration:1..x",
+        ] {
+            assert!(bad.parse::<FilterExpr>().is_err(), "{bad} should not parse");
+        }
+
+        assert!("".parse::<FilterExpr>().unwrap().is_empty());
+        assert!(!"  beach  ".parse::<FilterExpr>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn types() {
+        let mut video = image("clip.mp4", 1920, 1080, 2023, 5);
+        video.media_type = MediaType::Mp4;
+        video.is_video = true;
+        let still = image("a.png", 100, 100, 2023, 5);
+
+        assert!(matches("type:video", &video));
+        assert!(!matches("type:video", &still));
+        assert!(matches("type:image", &still));
+        assert!(matches("type:static", &still));
+        assert!(matches("type:gif,png", &still));
+        assert!(matches("-type:video", &still));
+    }
+
+    #[test]
+    fn sizes_and_orientation() {
+        let tall = image("tall.jpg", 1080, 1920, 2023, 5);
+
+        assert!(matches("min-width:1000 max-height:1920", &tall));
+        assert!(!matches("min-width:1920", &tall));
+        assert!(matches("orientation:portrait", &tall));
+        assert!(matches("orientation:landscape,portrait", &tall));
+        assert!(!matches("orientation:square", &tall));
+        assert!(!matches(
+            "orientation:portrait",
+            &image("unknown.mp4", 0, 0, 2023, 5)
+        ));
+    }
+
+    #[test]
+    fn aspect_ratios() {
+        let hd = image("hd.png", 1920, 1080, 2023, 5);
+        let laptop = image("laptop.png", 1366, 768, 2023, 5);
+        let old = image("old.png", 1024, 768, 2023, 5);
+
+        assert!(matches("aspect:16:9", &hd));
+        assert!(matches("aspect:16/9", &laptop));
+        assert!(!matches("aspect:16:9", &old));
+        assert!(matches("aspect:1.33", &old));
+        assert!(matches("aspect:4:3..16:9", &old));
+        assert!(matches("aspect:16:9..4:3", &old));
+        assert!(matches("aspect:1.5..", &hd));
+        assert!(!matches("aspect:..1.5", &hd));
+        assert!(!matches("aspect:1", &image("unknown.mp4", 0, 0, 2023, 5)));
+
+        for bad in ["aspect:wide", "aspect:16:0", "aspect:-1", "aspect:1..x"] {
+            assert!(bad.parse::<FilterExpr>().is_err(), "{bad} should not parse");
+        }
+    }
+
+    #[test]
+    fn shape_filter() {
+        let shape = ShapeFilter {
+            orientation: Some(Orientation::Landscape),
+            min_width: Some(1920),
+            ..Default::default()
+        };
+        let expr = shape.to_expr();
+        assert!(expr.matches(&image("hd.png", 1920, 1080, 2023, 5)));
+        assert!(!expr.matches(&image("small.png", 1280, 720, 2023, 5)));
+        assert!(!expr.matches(&image("tall.png", 1920, 3000, 2023, 5)));
+
+        let both = "name:*.png".parse::<FilterExpr>().unwrap().and(expr);
+        assert!(!both.matches(&image("hd.jpg", 1920, 1080, 2023, 5)));
+        assert!(ShapeFilter::default().to_expr().is_empty());
+    }
+
+    #[test]
+    fn dates() {
+        let may = image("a.png", 1, 1, 2023, 5);
+
+        assert!(matches("date:2023", &may));
+        assert!(matches("date:2023-05", &may));
+        assert!(matches("date:2023-05-15", &may));
+        assert!(!matches("date:2023-05-16", &may));
+        assert!(matches("date:2022..2023", &may));
+        assert!(matches("date:2023-05..", &may));
+        assert!(matches("date:..2023-05", &may));
+        assert!(!matches("date:2023-06..", &may));
+        assert!(!matches("date:..2023-04", &may));
+    }
+
+    #[test]
+    fn names_and_text() {
+        let beach = image("2024/Holiday/beach-01.jpg", 1, 1, 2024, 7);
+
+        assert!(matches("name:*beach*", &beach));
+        assert!(!matches("name:holiday*", &beach));
+        assert!(matches("path:2024/**", &beach));
+        assert!(matches("holiday", &beach));
+        assert!(matches("holiday -name:*.png", &beach));
+        assert!(!matches("holiday snow", &beach));
+        assert!(matches(
+            "type:image min-width:1 orientation:square date:2023..2024 name:*beach*",
+            &beach
+        ));
+    }
+
+    #[test]
+    fn favourites() {
+        let mut beach = image("beach.jpg", 1, 1, 2024, 7);
+        assert!(!matches("is:favourite", &beach));
+        assert!(matches("-is:favourite", &beach));
+
+        beach.favourite = true;
+        assert!(matches("is:favorite", &beach));
+    }
+
+    #[test]
+    fn culling() {
+        let mut beach = image("beach.jpg", 1, 1, 2024, 7);
+        assert!(matches("is:undecided", &beach));
+        assert!(!matches("is:kept,rejected", &beach));
+
+        beach.decision = Some(Decision::Reject);
+        assert!(matches("is:rejected", &beach));
+        assert!(matches("is:kept,rejected", &beach));
+        assert!(!matches("is:undecided", &beach));
+    }
+
+    #[test]
+    fn tags_and_albums() {
+        let mut beach = image("beach.jpg", 1, 1, 2024, 7);
+        beach.tags = vec!["Sunset".to_string()];
+        beach.albums = vec!["Summer".to_string()];
+
+        assert!(matches("tag:sunset", &beach));
+        assert!(matches("tag:snow,sunset album:summer", &beach));
+        assert!(!matches("-tag:sunset", &beach));
+        assert!(!matches("album:winter", &beach));
+    }
+
+    #[test]
+    fn ratings_and_labels() {
+        let mut beach = image("beach.jpg", 1, 1, 2024, 7);
+        assert!(matches("rating:0", &beach));
+        assert!(!matches("rating:1..", &beach));
+
+        beach.rating = Some(4);
+        beach.label = Some("Red".to_string());
+        assert!(matches("rating:4", &beach));
+        assert!(matches("rating:3..", &beach));
+        assert!(matches("rating:..1,4..5", &beach));
+        assert!(!matches("rating:..3", &beach));
+        assert!(matches("label:red,green", &beach));
+        assert!(!matches("-label:red", &beach));
+
+        beach.rating = Some(-1);
+        assert!(matches("rating:-1", &beach));
+        assert!(!matches("rating:0..", &beach));
+    }
+
+    #[test]
+    fn durations() {
+        let mut clip = image("clip.mp4", 1, 1, 2024, 7);
+        clip.is_video = true;
+        clip.duration_ms = 10_500;
+        let still = image("still.png", 1, 1, 2024, 7);
+
+        assert!(matches("duration:10", &clip));
+        assert!(!matches("duration:11", &clip));
+        assert!(matches("length:10..60", &clip));
+        assert!(matches("duration:10.5..", &clip));
+        assert!(!matches("duration:..10", &clip));
+        assert!(matches("duration:..5,10..", &clip));
+        assert!(matches("-duration:30..", &clip));
+
+        // Still images have no length.
+        assert!(matches("duration:0", &still));
+        assert!(!matches("duration:1..", &still));
+    }
+}
@@ -0,0 +1,184 @@
+use serde::Serialize;
+
+use crate::image_info::ImageInfo;
+
+/// A folder that has something to show in it or somewhere below it, for browsing a recursive scan folder by folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct Folder {
+    pub name: String,
+    /// Which of the command line paths the folder is under.
+    pub root: String,
+    /// The path from the root, separated by `/`. Empty for the root itself.
+    pub path: String,
+    /// How many files are directly in this folder.
+    pub count: usize,
+    /// How many files are in this folder and all the folders below it.
+    pub total: usize,
+    /// A url for a picture to represent the folder, the first image in it or below it.
+    pub cover: Option<String>,
+    pub folders: Vec<Folder>,
+    #[serde(skip)]
+    cover_is_video: bool,
+}
+
+impl Folder {
+    fn new(name: &str, root: &str, path: &str) -> Folder {
+        Folder {
+            name: name.to_string(),
+            root: root.to_string(),
+            path: path.to_string(),
+            count: 0,
+            total: 0,
+            cover: None,
+            folders: Vec::new(),
+            cover_is_video: false,
+        }
+    }
+
+    fn add(&mut self, image: &ImageInfo, posters: bool) {
+        self.total += 1;
+
+        // Videos only have a picture to show if there are poster frames, and even then a real image is preferred.
+        let better_cover = self.cover.is_none() || (self.cover_is_video && !image.is_video);
+        if better_cover {
+            if !image.is_video {
+                self.cover = Some(format!("/img/{}", image.url));
+                self.cover_is_video = false;
+            } else if posters {
+                self.cover = Some(format!("/poster/{}", image.url));
+                self.cover_is_video = true;
+            }
+        }
+
+        let below = if self.path.is_empty() {
+            image.folder()
+        } else {
+            image.folder()[self.path.len()..].trim_start_matches('/')
+        };
+        let Some(name) = below.split('/').next().filter(|n| !n.is_empty()) else {
+            self.count += 1;
+            return;
+        };
+
+        let index = match self.folders.iter().position(|f| f.name == name) {
+            Some(index) => index,
+            None => {
+                let path = if self.path.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}/{}", self.path, name)
+                };
+                self.folders.push(Folder::new(name, &self.root, &path));
+                self.folders.len() - 1
+            }
+        };
+        self.folders[index].add(image, posters);
+    }
+
+    fn sort(&mut self) {
+        self.folders.sort_by(|a, b| a.name.cmp(&b.name));
+        for folder in self.folders.iter_mut() {
+            folder.sort();
+        }
+    }
+}
+
+/// Builds the folder hierarchy under each root from the images that were found. Folders with nothing to show in them
+/// or below them are left out.
+pub fn folders(roots: &[String], images: &[ImageInfo], posters: bool) -> Vec<Folder> {
+    roots
+        .iter()
+        .map(|root| {
+            let mut top = Folder::new(root, root, "");
+            for image in images.iter().filter(|i| &i.root == root) {
+                top.add(image, posters);
+            }
+            top.sort();
+            top
+        })
+        .collect()
+}
+
+/// Whether an image is in the folder or somewhere below it. The folder is relative to the image's root.
+pub fn in_folder(image: &ImageInfo, folder: &str) -> bool {
+    let folder = folder.trim_matches('/');
+    if folder.is_empty() {
+        return true;
+    }
+
+    match image.folder().strip_prefix(folder) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::folders::{folders, in_folder};
+    use crate::image_info::ImageInfo;
+    use crate::tree::tests::simple_image;
+
+    fn image(relative_path: &str, is_video: bool) -> ImageInfo {
+        let mut image = simple_image(100);
+        image.root = "/pictures".to_string();
+        image.relative_path = relative_path.to_string();
+        image.url = relative_path.replace('/', "_");
+        image.is_video = is_video;
+        image
+    }
+
+    #[test]
+    fn hierarchy() {
+        let images = vec![
+            image("top.png", false),
+            image("2024/beach/clip.mp4", true),
+            image("2024/beach/sand.png", false),
+            image("2024/alps/snow.png", false),
+            image("2023/old.png", false),
+        ];
+        let roots = vec!["/pictures".to_string()];
+
+        let result = folders(&roots, &images, false);
+        assert_eq!(result.len(), 1);
+        let top = &result[0];
+        assert_eq!(top.count, 1);
+        assert_eq!(top.total, 5);
+        assert_eq!(top.cover.as_deref(), Some("/img/top.png"));
+
+        let names: Vec<&str> = top.folders.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["2023", "2024"]);
+
+        let year = &top.folders[1];
+        assert_eq!(year.count, 0);
+        assert_eq!(year.total, 3);
+        let beach = &year.folders[1];
+        assert_eq!(beach.path, "2024/beach");
+        assert_eq!(beach.count, 2);
+        // The image is preferred over the video that came first.
+        assert_eq!(beach.cover.as_deref(), Some("/img/2024_beach_sand.png"));
+    }
+
+    #[test]
+    fn video_covers() {
+        let images = vec![image("clips/clip.mp4", true)];
+        let roots = vec!["/pictures".to_string()];
+
+        assert_eq!(folders(&roots, &images, false)[0].folders[0].cover, None);
+        assert_eq!(
+            folders(&roots, &images, true)[0].folders[0]
+                .cover
+                .as_deref(),
+            Some("/poster/clips_clip.mp4")
+        );
+    }
+
+    #[test]
+    fn folder_scope() {
+        let sand = image("2024/beach/sand.png", false);
+        assert!(in_folder(&sand, ""));
+        assert!(in_folder(&sand, "2024"));
+        assert!(in_folder(&sand, "2024/beach/"));
+        assert!(!in_folder(&sand, "2024/bea"));
+        assert!(!in_folder(&sand, "2023"));
+    }
+}
@@ -0,0 +1,129 @@
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+
+use crate::image_info::ImageInfo;
+
+/// How the images are split into sections, each starting with a header.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    #[default]
+    None,
+    Folder,
+    Day,
+    Month,
+}
+
+impl std::fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+impl GroupBy {
+    /// Something that sorts the groups into order, the same for every image in a group.
+    pub fn key(&self, image: &ImageInfo) -> String {
+        match self {
+            GroupBy::None => String::new(),
+            GroupBy::Folder => format!("{}\0{}", image.root, image.folder()),
+            GroupBy::Day => local_date(image).format("%Y-%m-%d").to_string(),
+            GroupBy::Month => local_date(image).format("%Y-%m").to_string(),
+        }
+    }
+
+    pub fn label(&self, image: &ImageInfo) -> String {
+        match self {
+            GroupBy::None => String::new(),
+            GroupBy::Folder if image.folder().is_empty() => image.root.clone(),
+            GroupBy::Folder => image.folder().to_string(),
+            GroupBy::Day => local_date(image).format("%A %-d %B %Y").to_string(),
+            GroupBy::Month => local_date(image).format("%B %Y").to_string(),
+        }
+    }
+
+    /// Moves the images so each group is together, keeping the order of the images within a group. Dates go newest
+    /// first if asked for, otherwise the groups are in ascending order.
+    pub fn gather(&self, images: &mut [ImageInfo], newest_first: bool) {
+        match self {
+            GroupBy::None => {}
+            GroupBy::Day | GroupBy::Month if newest_first => {
+                images.sort_by_cached_key(|i| std::cmp::Reverse(self.key(i)))
+            }
+            _ => images.sort_by_cached_key(|i| self.key(i)),
+        }
+    }
+
+    /// Gives the first image of each group the header for its group, and clears it from every other image.
+    pub fn set_headers(&self, images: &mut [ImageInfo]) {
+        let mut previous = None;
+        for image in images.iter_mut() {
+            if *self == GroupBy::None {
+                image.header = None;
+                continue;
+            }
+
+            let key = self.key(image);
+            image.header = if previous.as_ref() != Some(&key) {
+                Some(self.label(image))
+            } else {
+                None
+            };
+            previous = Some(key);
+        }
+    }
+}
+
+fn local_date(image: &ImageInfo) -> DateTime<Local> {
+    DateTime::<Local>::from(image.date)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::group::GroupBy;
+    use crate::image_info::ImageInfo;
+    use crate::tree::tests::simple_image;
+
+    fn image(folder: &str, days_ago: u64) -> ImageInfo {
+        let mut image = simple_image(100);
+        image.root = "/pictures".to_string();
+        image.relative_path = format!("{folder}/{days_ago}.png");
+        image.url = image.relative_path.clone();
+        image.date = SystemTime::now() - Duration::from_secs(days_ago * 24 * 60 * 60);
+        image
+    }
+
+    fn headers(images: &[ImageInfo]) -> Vec<Option<&str>> {
+        images.iter().map(|i| i.header.as_deref()).collect()
+    }
+
+    #[test]
+    fn folder_groups() {
+        let mut images = vec![image("b", 1), image("a", 2), image("b", 3), image("a", 4)];
+        GroupBy::Folder.gather(&mut images, false);
+        GroupBy::Folder.set_headers(&mut images);
+
+        let urls: Vec<&str> = images.iter().map(|i| i.url.as_str()).collect();
+        assert_eq!(urls, vec!["a/2.png", "a/4.png", "b/1.png", "b/3.png"]);
+        assert_eq!(headers(&images), vec![Some("a"), None, Some("b"), None]);
+    }
+
+    #[test]
+    fn day_groups() {
+        let mut images = vec![image("a", 30), image("a", 1), image("b", 30)];
+        GroupBy::Day.gather(&mut images, true);
+        GroupBy::Day.set_headers(&mut images);
+
+        let urls: Vec<&str> = images.iter().map(|i| i.url.as_str()).collect();
+        assert_eq!(urls, vec!["a/1.png", "a/30.png", "b/30.png"]);
+        assert!(images[0].header.is_some());
+        assert!(images[1].header.is_some());
+        assert_eq!(images[2].header, None);
+
+        GroupBy::None.set_headers(&mut images);
+        assert_eq!(headers(&images), vec![None, None, None]);
+    }
+}
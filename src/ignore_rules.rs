@@ -0,0 +1,151 @@
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use log::warn;
+
+use crate::error::Error;
+
+/// The name of the per folder file listing things to leave out, using the same rules as a `.gitignore` file.
+pub const IGNORE_FILE_NAME: &str = ".pbignore";
+
+/// Decides which files and folders are skipped while scanning. Built from the `--exclude` and `--include` command line
+/// patterns plus any `.pbignore` files found on the way down.
+pub struct IgnoreRules {
+    excludes: Gitignore,
+    includes: Option<Gitignore>,
+    /// The rules from `.pbignore` files in the folders above the current one, closest last.
+    folders: Vec<Gitignore>,
+}
+
+impl IgnoreRules {
+    /// The command line patterns are relative to the folder being scanned.
+    pub fn new(
+        root: &Path,
+        excludes: &[String],
+        includes: &[String],
+    ) -> Result<IgnoreRules, Error> {
+        let includes = if includes.is_empty() {
+            None
+        } else {
+            Some(build(root, includes)?)
+        };
+
+        Ok(IgnoreRules {
+            excludes: build(root, excludes)?,
+            includes,
+            folders: Vec::new(),
+        })
+    }
+
+    /// Picks up the `.pbignore` file in a folder, if it has one. Returns a marker to hand to `leave` once the
+    /// folder has been scanned.
+    pub fn enter(&mut self, dir: &Path) -> usize {
+        let mark = self.folders.len();
+        let ignore_file = dir.join(IGNORE_FILE_NAME);
+        if ignore_file.is_file() {
+            let (rules, error) = Gitignore::new(&ignore_file);
+            if let Some(error) = error {
+                warn!(
+                    "Problem reading {:?}, some lines were skipped: {}",
+                    ignore_file, error
+                );
+            }
+            self.folders.push(rules);
+        }
+        mark
+    }
+
+    pub fn leave(&mut self, mark: usize) {
+        self.folders.truncate(mark);
+    }
+
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if self.excludes.matched(path, is_dir).is_ignore() {
+            return true;
+        }
+
+        // The closest .pbignore wins, so a folder can un-ignore something its parent ignored with a `!` pattern.
+        for rules in self.folders.iter().rev() {
+            match rules.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => break,
+                Match::None => {}
+            }
+        }
+
+        // Folders are always searched when there are include patterns, it's the files in them that need to match.
+        match &self.includes {
+            Some(includes) if !is_dir => !includes.matched(path, is_dir).is_ignore(),
+            _ => false,
+        }
+    }
+}
+
+fn build(root: &Path, patterns: &[String]) -> Result<Gitignore, Error> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder.add_line(None, pattern)?;
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use tempfile::tempdir;
+
+    use crate::ignore_rules::IgnoreRules;
+
+    fn patterns(list: &[&str]) -> Vec<String> {
+        list.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn command_line_patterns() {
+        let root = Path::new("/pictures");
+        let rules = IgnoreRules::new(root, &patterns(&["@eaDir", "*.tmp.jpg"]), &[]).unwrap();
+
+        assert!(rules.is_excluded(&root.join("@eaDir"), true));
+        assert!(rules.is_excluded(&root.join("holiday/@eaDir"), true));
+        assert!(rules.is_excluded(&root.join("holiday/a.tmp.jpg"), false));
+        assert!(!rules.is_excluded(&root.join("holiday/a.jpg"), false));
+    }
+
+    #[test]
+    fn include_patterns_only_apply_to_files() {
+        let root = Path::new("/pictures");
+        let rules = IgnoreRules::new(root, &[], &patterns(&["*beach*"])).unwrap();
+
+        assert!(!rules.is_excluded(&root.join("2024"), true));
+        assert!(!rules.is_excluded(&root.join("2024/beach.jpg"), false));
+        assert!(rules.is_excluded(&root.join("2024/mountain.jpg"), false));
+    }
+
+    #[test]
+    fn ignore_files() {
+        let root = tempdir().unwrap();
+        let holiday = root.path().join("holiday");
+        fs::create_dir(&holiday).unwrap();
+        fs::write(root.path().join(".pbignore"), "*.png\nthumbs/\n").unwrap();
+        fs::write(holiday.join(".pbignore"), "!keep.png\n").unwrap();
+
+        let mut rules = IgnoreRules::new(root.path(), &[], &[]).unwrap();
+        let root_mark = rules.enter(root.path());
+        assert!(rules.is_excluded(&root.path().join("a.png"), false));
+        assert!(rules.is_excluded(&root.path().join("thumbs"), true));
+        assert!(!rules.is_excluded(&root.path().join("a.jpg"), false));
+
+        let holiday_mark = rules.enter(&holiday);
+        assert!(rules.is_excluded(&holiday.join("b.png"), false));
+        assert!(!rules.is_excluded(&holiday.join("keep.png"), false));
+
+        rules.leave(holiday_mark);
+        assert!(rules.is_excluded(&root.path().join("keep.png"), false));
+
+        rules.leave(root_mark);
+        assert!(!rules.is_excluded(&root.path().join("a.png"), false));
+    }
+}
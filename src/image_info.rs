@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::fs;
 use std::fs::DirEntry;
 use std::fs::File;
@@ -11,116 +12,526 @@ use std::time::SystemTime;
 use imagesize::size;
 use log::info;
 use log::warn;
-use mp4::Mp4Reader;
+use mp4::{Mp4Reader, TrackType};
+use quick_xml::events::Event;
+use quick_xml::Reader;
 use serde::Serialize;
 use serde_json::Value;
 use uuid::Uuid;
 use which::which;
 
+use crate::animation::{animation_info, Animation};
+use crate::annotations::Annotations;
+use crate::culling::Decision;
 use crate::error::Error;
-use crate::FilterParameter;
+use crate::file_actions::TRASH_FOLDER;
+use crate::filter_expr::FilterExpr;
+use crate::ignore_rules::IgnoreRules;
+use crate::sniff::{sniff, MediaType};
+use crate::store::STORE_FOLDER;
+use crate::xmp::read_xmp;
+use crate::{FilterParameter, HEADER_HEIGHT, IMAGE_OFFSET};
 
-const ALLOWED_IMG_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp"];
+const ALLOWED_IMG_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "svg", "bmp", "tif", "tiff", "ico",
+];
 
 const ALLOWED_VID_EXTENSIONS: &[&str] = &["mp4", "webm"];
 
+/// Everything that controls which files are picked up when scanning the disk.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    pub filter: FilterParameter,
+    /// Applied to everything found, after the files have been read.
+    pub filter_expr: FilterExpr,
+    pub recursive: bool,
+    pub include_extensionless: bool,
+    /// Show files and folders that are hidden, dot files on unix or with the hidden attribute on windows.
+    pub include_hidden: bool,
+    /// gitignore style patterns for files and folders to leave out.
+    pub excludes: Vec<String>,
+    /// gitignore style patterns, if there are any only files matching one of them are shown.
+    pub includes: Vec<String>,
+    /// How many levels of sub folders to go down when recursing, no limit if not set.
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    /// Don't go into folders on a different file system to the one being scanned, like `find -xdev`.
+    pub one_file_system: bool,
+}
+
+/// A file or folder that was found but couldn't be read.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanProblem {
+    pub path: String,
+    /// Which `Error` variant stopped it being read.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// Everything that went wrong during a scan. A bad file is left out of the gallery rather than stopping the scan.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanReport {
+    pub problems: Vec<ScanProblem>,
+}
+
+impl ScanReport {
+    fn add(&mut self, path: &Path, error: &Error) {
+        warn!("Skipping {:?}: {}", path, error);
+        self.problems.push(ScanProblem {
+            path: path.to_string_lossy().to_string(),
+            kind: error.kind(),
+            message: error.to_string(),
+        });
+    }
+}
+
+/// Things that change as the scan works its way down the folders of one root.
+struct ScanState<'a> {
+    /// The root as it was given on the command line, copied to every image found under it.
+    root: &'a str,
+    rules: IgnoreRules,
+    report: &'a mut ScanReport,
+    /// Every folder that has been entered, so a symlink or bind mount pointing back up the tree can't loop forever.
+    /// Shared between the roots so overlapping roots don't show the same folder twice.
+    visited: &'a mut HashSet<FileId>,
+    root_device: Option<u64>,
+}
+
+/// Finds everything that can be shown under the roots, which can be folders or single files, merged into one list.
+/// Also returns a report of the files that couldn't be read. Favourites and tags are marked before filtering so the
+/// filter can use them.
 pub fn find_files(
-    target_path: &str,
-    filter_value: FilterParameter,
-    recurse: bool,
-) -> Vec<ImageInfo> {
-    let allow_list: Vec<&str> = match filter_value {
-        FilterParameter::None => ALLOWED_IMG_EXTENSIONS
-            .iter()
-            .copied()
-            .chain(ALLOWED_VID_EXTENSIONS.iter().copied())
-            .collect(),
-        FilterParameter::Video => ALLOWED_VID_EXTENSIONS.into(),
-        FilterParameter::Images => ALLOWED_IMG_EXTENSIONS.into(),
-        FilterParameter::Gif => vec!["gif"],
+    roots: &[String],
+    options: &ScanOptions,
+    annotations: &mut Annotations,
+) -> (Vec<ImageInfo>, ScanReport) {
+    let mut found = Vec::new();
+    let mut report = ScanReport::default();
+    let mut visited = HashSet::new();
+
+    for root in roots {
+        let target: PathBuf = root.into();
+        if target.is_file() {
+            // A file named on the command line is wanted whatever it is called, so the extension isn't checked.
+            found.extend(scan_file(&target, root, options, &mut report));
+            continue;
+        }
+
+        let rules = match IgnoreRules::new(&target, &options.excludes, &options.includes) {
+            Ok(rules) => rules,
+            Err(e) => {
+                warn!("Invalid exclude or include pattern, nothing will be found: {e}");
+                return (Vec::new(), report);
+            }
+        };
+        let mut state = ScanState {
+            root,
+            rules,
+            report: &mut report,
+            visited: &mut visited,
+            root_device: device(&target),
+        };
+
+        found.append(&mut inner_find_files(&target, options, &mut state, 0));
+    }
+
+    // Whether an image is animated can only be known after reading it, so these filters are applied afterwards.
+    let mut found: Vec<ImageInfo> = match options.filter {
+        FilterParameter::Animated => found.into_iter().filter(|i| i.animated).collect(),
+        FilterParameter::Static => found.into_iter().filter(|i| !i.animated).collect(),
+        _ => found,
     };
+    annotations.mark(&mut found);
+    if !options.filter_expr.is_empty() {
+        found.retain(|i| options.filter_expr.matches(i));
+    }
 
-    inner_find_files(&target_path.into(), &allow_list, recurse)
+    (found, report)
 }
 
-fn inner_find_files(target_dir: &PathBuf, allow_list: &Vec<&str>, recurse: bool) -> Vec<ImageInfo> {
+fn inner_find_files(
+    target_dir: &PathBuf,
+    options: &ScanOptions,
+    state: &mut ScanState,
+    depth: usize,
+) -> Vec<ImageInfo> {
     let mut result = Vec::new();
     if target_dir.is_dir() {
-        if let Ok(entries) = fs::read_dir(target_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_dir() && recurse {
-                    let mut new_files = inner_find_files(&path, allow_list, recurse);
+        if let Some(id) = file_id(target_dir) {
+            if !state.visited.insert(id) {
+                warn!(
+                    "{:?} has already been scanned, skipping it to avoid a loop",
+                    target_dir
+                );
+                return result;
+            }
+        }
+
+        let entries = match fs::read_dir(target_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                state.report.add(target_dir, &e.into());
+                return result;
+            }
+        };
+        let mark = state.rules.enter(target_dir);
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if is_apple_double(&entry)
+                || is_our_folder(&entry)
+                || (!options.include_hidden && is_hidden(&entry))
+            {
+                continue;
+            }
+
+            let is_dir = path.is_dir();
+            // Checked before anything else so excluded folders are never read.
+            if state.rules.is_excluded(&path, is_dir) {
+                info!("excluded {:?}", path);
+                continue;
+            }
+
+            if is_dir && options.recursive {
+                if should_enter(&entry, options, state, depth) {
+                    let mut new_files = inner_find_files(&path, options, state, depth + 1);
                     result.append(&mut new_files);
-                } else if path.is_file() {
-                    let extension = path
-                        .extension()
-                        .map(|p| p.to_str().unwrap())
-                        .unwrap_or("")
-                        .to_lowercase();
-                    if allow_list.contains(&extension.as_str()) {
-                        result.push(file_to_image(&entry).unwrap())
-                    } else {
-                        warn!("disallowed file type {:?}", entry.file_name());
-                    }
                 }
+            } else if path.is_file() {
+                if !is_candidate(&path, options) {
+                    warn!("disallowed file type {:?}", entry.file_name());
+                    continue;
+                }
+
+                result.extend(scan_file(&path, state.root, options, state.report));
             }
         }
+        state.rules.leave(mark);
     }
 
     result
 }
 
+/// Reads a single file, returning `None` if it isn't something that can be shown or is left out by the filter.
+fn scan_file(
+    path: &Path,
+    root: &str,
+    options: &ScanOptions,
+    report: &mut ScanReport,
+) -> Option<ImageInfo> {
+    let media_type = match media_type(path) {
+        Ok(Some(media_type)) => media_type,
+        Ok(None) => {
+            warn!("unrecognised file contents {:?}", path);
+            return None;
+        }
+        Err(e) => {
+            report.add(path, &e);
+            return None;
+        }
+    };
+
+    if !filter_allows(options.filter, media_type) {
+        return None;
+    }
+
+    match file_to_image(path, root, media_type) {
+        Ok(image) => Some(image),
+        Err(e) => {
+            report.add(path, &e);
+            None
+        }
+    }
+}
+
+fn should_enter(entry: &DirEntry, options: &ScanOptions, state: &ScanState, depth: usize) -> bool {
+    if options.max_depth.is_some_and(|max| depth >= max) {
+        info!(
+            "{:?} is deeper than the maximum depth, skipping it",
+            entry.path()
+        );
+        return false;
+    }
+
+    let is_symlink = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+    if is_symlink && !options.follow_symlinks {
+        info!("{:?} is a symlink, skipping it", entry.path());
+        return false;
+    }
+
+    if options.one_file_system && device(&entry.path()) != state.root_device {
+        info!(
+            "{:?} is on a different file system, skipping it",
+            entry.path()
+        );
+        return false;
+    }
+
+    true
+}
+
+/// macOS leaves `._` files next to pictures when copying to other file systems. They hold the resource fork, not a
+/// picture, even though they have the picture's extension, so they are always skipped.
+fn is_apple_double(entry: &DirEntry) -> bool {
+    entry.file_name().as_encoded_bytes().starts_with(b"._")
+}
+
+/// The trash and the store, which are never shown even with hidden files.
+fn is_our_folder(entry: &DirEntry) -> bool {
+    let name = entry.file_name();
+    name == TRASH_FOLDER || name == STORE_FOLDER
+}
+
+#[cfg(not(windows))]
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().as_encoded_bytes().starts_with(b".")
+}
+
+#[cfg(windows)]
+fn is_hidden(entry: &DirEntry) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    entry.file_name().as_encoded_bytes().starts_with(b".")
+        || entry
+            .metadata()
+            .map(|m| m.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+            .unwrap_or(false)
+}
+
+/// Identifies a folder no matter which path was used to reach it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FileId {
+    #[cfg(unix)]
+    Inode { device: u64, inode: u64 },
+    #[cfg(not(unix))]
+    Canonical(PathBuf),
+}
+
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path).ok()?;
+    Some(FileId::Inode {
+        device: metadata.dev(),
+        inode: metadata.ino(),
+    })
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path) -> Option<FileId> {
+    fs::canonicalize(path).ok().map(FileId::Canonical)
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path).ok().map(|m| m.dev())
+}
+
+/// There isn't a portable way to get the device outside of unix, so everything counts as the same file system.
+#[cfg(not(unix))]
+fn device(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Only files with one of the allowed extensions (or no extension at all, if asked for) are worth opening to find
+/// out what they really are.
+fn is_candidate(path: &Path, options: &ScanOptions) -> bool {
+    match path.extension() {
+        Some(extension) => {
+            let extension = extension.to_string_lossy().to_lowercase();
+            ALLOWED_IMG_EXTENSIONS.contains(&extension.as_str())
+                || ALLOWED_VID_EXTENSIONS.contains(&extension.as_str())
+        }
+        None => options.include_extensionless,
+    }
+}
+
+/// Decides the type from the file contents. Svg is text and may have a long preamble before the root element, so a
+/// file named svg is trusted even if the start of it wasn't recognised.
+fn media_type(path: &Path) -> Result<Option<MediaType>, Error> {
+    let from_extension = path
+        .extension()
+        .and_then(|e| MediaType::from_extension(&e.to_string_lossy()));
+    let sniffed = sniff(path)?;
+
+    if let (Some(sniffed), Some(from_extension)) = (sniffed, from_extension) {
+        if sniffed != from_extension {
+            info!(
+                "{:?} has the wrong extension, it is really a {} file",
+                path,
+                sniffed.extension()
+            );
+        }
+    }
+
+    Ok(sniffed.or(from_extension.filter(|t| *t == MediaType::Svg)))
+}
+
+fn filter_allows(filter: FilterParameter, media_type: MediaType) -> bool {
+    match filter {
+        FilterParameter::None => true,
+        FilterParameter::Video => media_type.is_video(),
+        FilterParameter::Images | FilterParameter::Static => !media_type.is_video(),
+        FilterParameter::Gif => media_type == MediaType::Gif,
+        FilterParameter::Animated => media_type.can_animate(),
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct ImageInfo {
     pub url: String,
     pub source: String,
+    /// The real path of the file, `source` is only for display as it may have lost characters that aren't valid utf-8.
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Which of the paths given on the command line this was found under.
+    pub root: String,
+    /// The path from the root to the file, separated by `/` on every platform. Just the file name for a root that is
+    /// a single file.
+    pub relative_path: String,
+    pub media_type: MediaType,
     pub date: SystemTime,
     pub is_video: bool,
+    pub animated: bool,
+    pub frame_count: u32,
+    /// How long an animation or video plays for, zero for still images or if it couldn't be found.
+    pub duration_ms: u64,
+    pub codec: Option<String>,
+    pub frame_rate: Option<f64>,
+    pub has_audio: bool,
     pub width: u64,
     pub height: u64,
+    /// From xmp, 1 to 5 stars, 0 for unrated and -1 for rejected.
+    pub rating: Option<i8>,
+    /// The colour label from xmp, E.G. `Red`.
+    pub label: Option<String>,
+    /// Set from the favourites kept for the root, not the file itself.
+    pub favourite: bool,
+    pub tags: Vec<String>,
+    pub albums: Vec<String>,
+    /// Whether the file was kept or rejected while culling, if it's been looked at.
+    pub decision: Option<Decision>,
+    /// The heading of the group this starts, when the images are grouped.
+    pub header: Option<String>,
     pub height_before: u64,
     pub height_after: u64,
 }
 
-fn file_to_image(entry: &DirEntry) -> Result<ImageInfo, Error> {
-    let filepath = entry.path().to_str().unwrap().to_string();
-    let p = Path::new(&filepath);
+/// The same file under the same root always gets the same id. The root is part of it as the same file can be found
+/// under two roots that overlap.
+fn stable_id(root: &str, path: &Path) -> Uuid {
+    let mut name = root.as_bytes().to_vec();
+    name.push(0);
+    name.extend(path.as_os_str().as_encoded_bytes());
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, &name)
+}
+
+/// What the page knows a file by, with the extension of the type it was found to be.
+pub fn image_url(root: &str, path: &Path, media_type: MediaType) -> String {
+    format!("{}.{}", stable_id(root, path), media_type.extension())
+}
+
+fn file_to_image(path: &Path, root: &str, media_type: MediaType) -> Result<ImageInfo, Error> {
+    let path = path.to_path_buf();
 
     // Generate a uuid with the file extension here so that if we are running in recursive mode
     // and two folders contain the same file name we don't end up with duplicate entries.
-    let extension = p.extension().map(|p| p.to_str().unwrap()).unwrap_or("");
-    let uuid = Uuid::new_v4();
-    let url = format!("{}.{}", uuid, extension);
+    // It comes from the root and the path so it stays the same between restarts, and links to /view/ keep working.
+    // The extension comes from the detected type so a misnamed file gets the right one.
+    let url = image_url(root, &path, media_type);
 
-    let metadata = entry.metadata()?;
+    let metadata = fs::metadata(&path)?;
     let date = date(&metadata)?;
 
-    let is_video = ALLOWED_VID_EXTENSIONS.contains(&extension);
+    let is_video = media_type.is_video();
 
-    let (width, height) = if is_video {
-        // we have a way of handling some mp4 files. Others need working on.
-        if extension == "mp4" {
-            mp4_size(&filepath)?
-        } else {
-            (0, 0)
-        }
+    let video = if is_video {
+        video_info(&path, media_type)
     } else {
-        image_size(&filepath)?
+        VideoInfo::default()
     };
 
+    let (width, height) = match media_type {
+        MediaType::Mp4 | MediaType::Webm => (video.width, video.height),
+        MediaType::Svg => svg_size(&path)?,
+        _ => image_size(&path)?,
+    };
+
+    let animation = if media_type.can_animate() {
+        animation_info(&path, media_type).unwrap_or_else(|e| {
+            warn!("Could not read the frames of {path:?}, assuming it is static. {e}");
+            Animation::STATIC
+        })
+    } else {
+        Animation::STATIC
+    };
+
+    let xmp = read_xmp(&path, media_type);
+    let relative_path = relative_path(&path, root);
     Ok(ImageInfo {
         url,
-        source: p.to_str().unwrap().to_string(),
+        source: path.to_string_lossy().to_string(),
+        path,
+        root: root.to_string(),
+        relative_path,
+        media_type,
         date,
         is_video,
+        animated: animation.is_animated(),
+        frame_count: animation.frame_count,
+        duration_ms: animation.duration_ms.max(video.duration_ms),
+        codec: video.codec,
+        frame_rate: video.frame_rate,
+        has_audio: video.has_audio,
         width,
         height,
+        rating: xmp.rating,
+        label: xmp.label,
+        favourite: false,
+        tags: Vec::new(),
+        albums: Vec::new(),
+        decision: None,
+        header: None,
         height_before: 0,
         height_after: 0,
     })
 }
 
+fn relative_path(path: &Path, root: &str) -> String {
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative,
+        _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+    };
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl ImageInfo {
+    /// How much room this takes up in the page, including the gap and any group header above it.
+    pub fn layout_height(&self) -> u64 {
+        let header = if self.header.is_some() {
+            HEADER_HEIGHT
+        } else {
+            0
+        };
+        self.height + IMAGE_OFFSET + header
+    }
+
+    /// The folder the file is in relative to its root, empty if it is directly in the root.
+    pub fn folder(&self) -> &str {
+        match self.relative_path.rsplit_once('/') {
+            Some((folder, _)) => folder,
+            None => "",
+        }
+    }
+}
+
 fn date(metadata: &Metadata) -> Result<SystemTime, Error> {
     metadata
         .modified()
@@ -128,40 +539,163 @@ fn date(metadata: &Metadata) -> Result<SystemTime, Error> {
         .or(Ok(metadata.accessed()?))
 }
 
-fn image_size(filepath: &str) -> Result<(u64, u64), Error> {
+fn image_size(filepath: &Path) -> Result<(u64, u64), Error> {
     let result = size(filepath)?;
     Ok((result.width as u64, result.height as u64))
 }
 
-fn mp4_size(filepath: &str) -> Result<(u64, u64), Error> {
+fn svg_size(filepath: &Path) -> Result<(u64, u64), Error> {
+    let contents = fs::read_to_string(filepath)?;
+    match parse_svg_size(&contents)? {
+        Some(size) => Ok(size),
+        None => {
+            warn!(
+                "No usable width, height or viewBox in {filepath:?}, using the browser default size"
+            );
+            Ok(SVG_DEFAULT_SIZE)
+        }
+    }
+}
+
+/// Browsers display an svg with no size information at 300x150, so we do the same.
+const SVG_DEFAULT_SIZE: (u64, u64) = (300, 150);
+
+/// Reads the size of an svg from the attributes on its root element. Absolute width and height win, otherwise the
+/// viewBox is used, scaled to match width or height if only one of them was given.
+fn parse_svg_size(contents: &str) -> Result<Option<(u64, u64)>, Error> {
+    let mut reader = Reader::from_str(contents);
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"svg" => {
+                let mut width = None;
+                let mut height = None;
+                let mut view_box = None;
+                for attribute in e.attributes() {
+                    let attribute = attribute.map_err(quick_xml::Error::from)?;
+                    let value = attribute.unescape_value()?;
+                    match attribute.key.local_name().as_ref() {
+                        b"width" => width = svg_length(&value),
+                        b"height" => height = svg_length(&value),
+                        b"viewBox" => view_box = svg_view_box(&value),
+                        _ => {}
+                    }
+                }
+
+                let size = match (width, height, view_box) {
+                    (Some(w), Some(h), _) => Some((w, h)),
+                    (Some(w), None, Some((vw, vh))) => Some((w, w * vh / vw)),
+                    (None, Some(h), Some((vw, vh))) => Some((h * vw / vh, h)),
+                    (None, None, Some(view_box)) => Some(view_box),
+                    _ => None,
+                };
+                return Ok(size
+                    .map(|(w, h)| (w.round() as u64, h.round() as u64))
+                    .filter(|(w, h)| *w > 0 && *h > 0));
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+/// Converts an svg length to pixels. Percentages and font relative units can't be resolved without a page, so they
+/// are treated as missing.
+fn svg_length(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let scale = match unit.trim() {
+        "" | "px" => 1.0,
+        "pt" => 96.0 / 72.0,
+        "pc" => 16.0,
+        "in" => 96.0,
+        "cm" => 96.0 / 2.54,
+        "mm" => 96.0 / 25.4,
+        _ => return None,
+    };
+    Some(number * scale).filter(|n| *n > 0.0)
+}
+
+fn svg_view_box(value: &str) -> Option<(f64, f64)> {
+    let parts: Vec<f64> = value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.parse())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match parts.as_slice() {
+        [_, _, w, h] if *w > 0.0 && *h > 0.0 => Some((*w, *h)),
+        _ => None,
+    }
+}
+
+/// What we know about a video beyond its size. Anything we couldn't find out is left empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct VideoInfo {
+    width: u64,
+    height: u64,
+    duration_ms: u64,
+    codec: Option<String>,
+    frame_rate: Option<f64>,
+    has_audio: bool,
+}
+
+fn video_info(filepath: &Path, media_type: MediaType) -> VideoInfo {
+    // we have a way of handling some mp4 files. Others need ffmpeg.
+    let result = match media_type {
+        MediaType::Mp4 => mp4_info(filepath).or_else(|mp4_error| {
+            warn!("Could not get metadata from {filepath:?}, attempting ffmpeg. {mp4_error:?}");
+            try_ffmpeg(filepath)
+        }),
+        _ => try_ffmpeg(filepath),
+    };
+
+    result.unwrap_or_else(|ffmpeg_err| {
+        warn!(
+            "Could not get metadata with ffmpeg either {filepath:?}. Returning zeros. {ffmpeg_err}"
+        );
+        VideoInfo::default()
+    })
+}
+
+fn mp4_info(filepath: &Path) -> Result<VideoInfo, Error> {
     let f = File::open(filepath)?;
     let size = f.metadata()?.len();
     let reader = BufReader::new(f);
 
-    let mp4_result = Mp4Reader::read_header(reader, size);
-    if let Err(mp4_error) = mp4_result {
-        warn!("Could not get metadata from {filepath}, attempting ffmpeg. {mp4_error:?}");
-        let ffmpeg_result = try_ffmpeg(filepath);
-        if let Err(ffmpeg_err) = ffmpeg_result {
-            warn!("Could not get metadata with ffmpeg either {filepath}. Returning zeros. {ffmpeg_err}");
-            return Ok((0, 0));
-        }
+    let mp4 = Mp4Reader::read_header(reader, size)?;
 
-        return ffmpeg_result;
-    }
+    let mut result = VideoInfo {
+        duration_ms: mp4.duration().as_millis() as u64,
+        ..Default::default()
+    };
 
-    let mut result_width = 0;
-    let mut result_height = 0;
+    for track in mp4.tracks().values() {
+        result.width = result.width.max(track.width() as u64);
+        result.height = result.height.max(track.height() as u64);
 
-    for track in mp4_result.unwrap().tracks().values() {
-        result_width = result_width.max(track.width());
-        result_height = result_height.max(track.height());
+        match track.track_type() {
+            Ok(TrackType::Video) if result.codec.is_none() => {
+                result.codec = track.media_type().ok().map(|m| m.to_string());
+                // The reader's own frame rate is rounded to a whole number, which is wrong for 29.97 and friends.
+                let track_ms = track.duration().as_millis();
+                if track_ms > 0 {
+                    result.frame_rate =
+                        Some(track.sample_count() as f64 * 1000.0 / track_ms as f64);
+                }
+            }
+            Ok(TrackType::Audio) => result.has_audio = true,
+            _ => {}
+        }
     }
 
-    Ok((result_width as u64, result_height as u64))
+    Ok(result)
 }
 
-fn try_ffmpeg(filepath: &str) -> Result<(u64, u64), Error> {
+fn try_ffmpeg(filepath: &Path) -> Result<VideoInfo, Error> {
     let which_result = which("ffprobe");
     if let Err(err) = which_result {
         warn!(
@@ -172,31 +706,344 @@ fn try_ffmpeg(filepath: &str) -> Result<(u64, u64), Error> {
     }
 
     let output = Command::new("ffprobe")
+        .arg("-i")
+        .arg(filepath)
         .args([
-            "-i",
-            filepath,
             "-print_format",
             "json",
             "-find_stream_info",
             "-show_streams",
+            "-show_format",
             "-v",
             "quiet",
         ])
         .output()?;
 
     let json_result: Value = serde_json::from_slice(&output.stdout)?;
+    let result = parse_ffprobe(&json_result);
+
+    info!("success using ffmpeg: {},{}", result.width, result.height);
+    Ok(result)
+}
 
-    let mut width: u64 = 0;
-    let mut height: u64 = 0;
+fn parse_ffprobe(json_result: &Value) -> VideoInfo {
+    let mut result = VideoInfo::default();
 
     for stream in json_result["streams"].as_array().unwrap_or(&Vec::new()) {
-        let stream_object = stream.as_object().unwrap();
-        if stream_object.contains_key("width") && stream_object.contains_key("height") {
-            width = width.max(stream_object.get("width").unwrap().as_u64().unwrap());
-            height = height.max(stream_object.get("height").unwrap().as_u64().unwrap());
+        match stream["codec_type"].as_str() {
+            Some("video") => {
+                result.width = result.width.max(stream["width"].as_u64().unwrap_or(0));
+                result.height = result.height.max(stream["height"].as_u64().unwrap_or(0));
+                if result.codec.is_none() {
+                    result.codec = stream["codec_name"].as_str().map(|c| c.to_string());
+                    result.frame_rate = stream["avg_frame_rate"]
+                        .as_str()
+                        .and_then(parse_frame_rate)
+                        .or_else(|| stream["r_frame_rate"].as_str().and_then(parse_frame_rate));
+                }
+            }
+            Some("audio") => result.has_audio = true,
+            _ => {}
+        }
+    }
+
+    // ffprobe gives durations as a string of seconds
+    result.duration_ms = json_result["format"]["duration"]
+        .as_str()
+        .and_then(|d| d.parse::<f64>().ok())
+        .map(|seconds| (seconds * 1000.0).round() as u64)
+        .unwrap_or(0);
+
+    result
+}
+
+/// ffprobe gives frame rates as a fraction, e.g. "30000/1001"
+fn parse_frame_rate(value: &str) -> Option<f64> {
+    let (numerator, denominator) = value.split_once('/')?;
+    let numerator: f64 = numerator.parse().ok()?;
+    let denominator: f64 = denominator.parse().ok()?;
+    (numerator > 0.0 && denominator > 0.0).then(|| numerator / denominator)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use crate::annotations::Annotations;
+    use crate::file_actions::TRASH_FOLDER;
+    use crate::image_info::{find_files, parse_ffprobe, parse_svg_size, ScanOptions, VideoInfo};
+    use crate::store::STORE_FOLDER;
+
+    /// Writes a 1x1 png, enough for the size and frame readers. Nothing checks the crc.
+    pub fn write_png(path: &Path) {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [
+            (&b"IHDR"[..], &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0][..]),
+            (b"IDAT", &[0x78, 0x9C, 0x62, 0, 0, 0, 0, 0, 1]),
+            (b"IEND", &[]),
+        ] {
+            png.extend((data.len() as u32).to_be_bytes());
+            png.extend(kind);
+            png.extend(data);
+            png.extend([0, 0, 0, 0]);
+        }
+        fs::write(path, png).unwrap();
+    }
+
+    fn found_names(root: &Path, options: &ScanOptions) -> Vec<String> {
+        let mut names: Vec<String> = find_files(
+            &[root.to_string_lossy().to_string()],
+            options,
+            &mut Annotations::default(),
+        )
+        .0
+        .iter()
+        .map(|i| {
+            i.path
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn max_depth() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("a/b")).unwrap();
+        write_png(&root.path().join("top.png"));
+        write_png(&root.path().join("a/one.png"));
+        write_png(&root.path().join("a/b/two.png"));
+
+        let mut options = ScanOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(found_names(root.path(), &options).len(), 3);
+
+        options.max_depth = Some(1);
+        assert_eq!(
+            found_names(root.path(), &options),
+            vec!["a/one.png", "top.png"]
+        );
+
+        options.max_depth = Some(0);
+        assert_eq!(found_names(root.path(), &options), vec!["top.png"]);
+    }
+
+    #[test]
+    fn bad_files_are_reported() {
+        let root = tempdir().unwrap();
+        write_png(&root.path().join("good.png"));
+        // Just the signature, cut off before the size.
+        fs::write(root.path().join("truncated.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let (images, report) = find_files(
+            &[root.path().to_string_lossy().to_string()],
+            &ScanOptions::default(),
+            &mut Annotations::default(),
+        );
+        assert_eq!(images.len(), 1);
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].path.ends_with("truncated.png"));
+        assert_eq!(report.problems[0].kind, "ImageSize");
+    }
+
+    #[test]
+    fn ids_stay_the_same() {
+        let root = tempdir().unwrap();
+        write_png(&root.path().join("a.png"));
+        write_png(&root.path().join("b.png"));
+        let roots = [root.path().to_string_lossy().to_string()];
+        let scan = || {
+            let mut urls: Vec<String> =
+                find_files(&roots, &ScanOptions::default(), &mut Annotations::default())
+                    .0
+                    .into_iter()
+                    .map(|i| i.url)
+                    .collect();
+            urls.sort();
+            urls
+        };
+
+        let first = scan();
+        assert_eq!(first, scan());
+        assert_ne!(first[0], first[1]);
+        assert!(first[0].ends_with(".png"));
+    }
+
+    #[test]
+    fn multiple_roots() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        fs::create_dir(first.path().join("sub")).unwrap();
+        write_png(&first.path().join("a.png"));
+        write_png(&first.path().join("sub/b.png"));
+        write_png(&second.path().join("c.png"));
+        // A single file is shown even without an allowed extension.
+        write_png(&second.path().join("d.data"));
+
+        let first_root = first.path().to_string_lossy().to_string();
+        let second_root = second.path().to_string_lossy().to_string();
+        let single_file = second.path().join("d.data").to_string_lossy().to_string();
+        let options = ScanOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        // The sub folder is inside the first root, so it is only scanned once.
+        let roots = [
+            first_root.clone(),
+            format!("{first_root}/sub"),
+            second_root.clone(),
+            single_file.clone(),
+        ];
+        let (images, _) = find_files(&roots, &options, &mut Annotations::default());
+
+        let mut found: Vec<(String, String)> = images
+            .iter()
+            .map(|i| (i.relative_path.clone(), i.root.clone()))
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                ("a.png".to_string(), first_root.clone()),
+                ("c.png".to_string(), second_root),
+                ("d.data".to_string(), single_file),
+                ("sub/b.png".to_string(), first_root),
+            ]
+        );
+    }
+
+    #[test]
+    fn hidden_files() {
+        let root = tempdir().unwrap();
+        fs::create_dir(root.path().join(".thumbnails")).unwrap();
+        write_png(&root.path().join("shown.png"));
+        write_png(&root.path().join(".hidden.png"));
+        write_png(&root.path().join(".thumbnails/thumb.png"));
+        for folder in [TRASH_FOLDER, STORE_FOLDER] {
+            fs::create_dir(root.path().join(folder)).unwrap();
+            write_png(&root.path().join(folder).join("gone.png"));
         }
+        fs::write(
+            root.path().join("._shown.png"),
+            b"\0\x05\x16\x07resource fork",
+        )
+        .unwrap();
+
+        let mut options = ScanOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(found_names(root.path(), &options), vec!["shown.png"]);
+
+        options.include_hidden = true;
+        assert_eq!(
+            found_names(root.path(), &options),
+            vec![".hidden.png", ".thumbnails/thumb.png", "shown.png"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_not_followed() {
+        let root = tempdir().unwrap();
+        let outside = tempdir().unwrap();
+        write_png(&outside.path().join("elsewhere.png"));
+        std::os::unix::fs::symlink(outside.path(), root.path().join("link")).unwrap();
+
+        let mut options = ScanOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert!(found_names(root.path(), &options).is_empty());
+
+        options.follow_symlinks = true;
+        assert_eq!(
+            found_names(root.path(), &options),
+            vec!["link/elsewhere.png"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop() {
+        let root = tempdir().unwrap();
+        fs::create_dir(root.path().join("a")).unwrap();
+        write_png(&root.path().join("a/one.png"));
+        // a/up points back at the root, and a/again at a, following them would never end
+        std::os::unix::fs::symlink(root.path(), root.path().join("a/up")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("a"), root.path().join("a/again")).unwrap();
+
+        let options = ScanOptions {
+            recursive: true,
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        assert_eq!(found_names(root.path(), &options), vec!["a/one.png"]);
     }
 
-    info!("success using ffmpeg: {},{}", width, height);
-    return Ok((width, height));
+    #[test]
+    fn ffprobe_video_and_audio() {
+        let probe = json!({
+            "streams": [
+                {"codec_type": "video", "codec_name": "vp9", "width": 1920, "height": 1080,
+                 "avg_frame_rate": "30000/1001", "r_frame_rate": "30/1"},
+                {"codec_type": "audio", "codec_name": "opus"}
+            ],
+            "format": {"duration": "12.345000"}
+        });
+        let result = parse_ffprobe(&probe);
+        assert_eq!(result.width, 1920);
+        assert_eq!(result.height, 1080);
+        assert_eq!(result.duration_ms, 12345);
+        assert_eq!(result.codec.as_deref(), Some("vp9"));
+        assert!((result.frame_rate.unwrap() - 29.97).abs() < 0.01);
+        assert!(result.has_audio);
+    }
+
+    #[test]
+    fn ffprobe_empty() {
+        assert_eq!(parse_ffprobe(&json!({})), VideoInfo::default());
+    }
+
+    #[test]
+    fn svg_width_and_height() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="640" height="480px"></svg>"#;
+        assert_eq!(parse_svg_size(svg).unwrap(), Some((640, 480)));
+    }
+
+    #[test]
+    fn svg_view_box_only() {
+        let svg = r#"<?xml version="1.0"?>
+            <!-- a comment -->
+            <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100.5 50"/>"#;
+        assert_eq!(parse_svg_size(svg).unwrap(), Some((101, 50)));
+    }
+
+    #[test]
+    fn svg_width_scaled_by_view_box() {
+        let svg = r#"<svg width="200" height="100%" viewBox="0,0,100,50"></svg>"#;
+        assert_eq!(parse_svg_size(svg).unwrap(), Some((200, 100)));
+    }
+
+    #[test]
+    fn svg_units() {
+        let svg = r#"<svg width="1in" height="72pt"></svg>"#;
+        assert_eq!(parse_svg_size(svg).unwrap(), Some((96, 96)));
+    }
+
+    #[test]
+    fn svg_no_size() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg"><rect/></svg>"#;
+        assert_eq!(parse_svg_size(svg).unwrap(), None);
+    }
 }
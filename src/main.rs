@@ -1,42 +1,94 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
-use actix_files::NamedFile;
 use actix_web::body::BoxBody;
 use log::{info, warn};
 
-use actix_web::http::header::ContentType;
+use actix_web::http::header::{self, ContentType, HeaderValue};
 use actix_web::middleware::Logger;
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder, Result};
 use clap::{Parser, ValueEnum};
 use env_logger::Env;
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 use tera::{Context, Tera};
 use tokio::task::JoinSet;
 
+use crate::annotations::Annotations;
+use crate::contact_sheet::{contact_sheet, thumbnail_size, DEFAULT_TILE, MAX_TILE, MIN_TILE};
+use crate::culling::{move_rejects, plan_rejects, record_moves, Applied, Culling, Decision};
+use crate::duplicates::{find_duplicates, HashCache, Method, DEFAULT_DISTANCE};
 use crate::error::Error;
-use crate::image_info::{find_files, ImageInfo};
+use crate::file_actions::{FileActions, TrashKind};
+use crate::filter_expr::{FilterExpr, ShapeFilter};
+use crate::folders::{folders, in_folder};
+use crate::group::GroupBy;
+use crate::ignore_rules::IgnoreRules;
+use crate::image_info::{find_files, ImageInfo, ScanOptions, ScanReport};
 #[cfg(feature = "post")]
 use crate::post::send_message;
+use crate::range::MediaFile;
+use crate::search::{search, SearchMode};
+use crate::slideshow::{
+    image_ms, now_ms, Position, Slideshow, SlideshowOptions, Transition, DEFAULT_IMAGE_SECONDS,
+};
+use crate::sniff::MediaType;
+use crate::timeline::{timeline, Bucket};
 use crate::tree::{TreeNode, TreeNodeLayer};
 
+pub mod animation;
+pub mod annotations;
+pub mod contact_sheet;
+pub mod convert;
+pub mod culling;
+pub mod duplicates;
 pub mod error;
+pub mod favourites;
+pub mod file_actions;
+pub mod filter_expr;
+pub mod folders;
+pub mod group;
+pub mod ignore_rules;
 pub mod image_info;
 #[cfg(feature = "post")]
 pub mod post;
+pub mod range;
+pub mod search;
+pub mod slideshow;
+pub mod sniff;
+pub mod store;
+pub mod tags;
+pub mod timeline;
 pub mod tree;
+pub mod xmp;
 
 struct AppData {
-    target_path: String,
+    target_paths: Vec<String>,
     images: Vec<ImageInfo>,
-    tree: TreeNode,
+    /// None once every image has been deleted.
+    tree: Option<TreeNode>,
     sort: SortBy,
-    filter: FilterParameter,
-    recursive: bool,
+    group_by: GroupBy,
+    scan: ScanOptions,
+    /// The files that couldn't be read last time the disk was scanned.
+    scan_report: ScanReport,
+    /// Favourites, tags and albums.
+    annotations: Annotations,
     templates: Tera,
     background: String,
     hot_reload: bool,
+    posters: bool,
+    /// Whether ratings can be saved to xmp sidecars.
+    write_xmp: bool,
+    /// Deleting, moving and renaming, only with `--allow-write`.
+    file_actions: Option<FileActions>,
+    /// Filled in the first time duplicates are looked for.
+    hashes: HashCache,
+    /// The show every screen on `/slideshow` follows, once one has been started.
+    slideshow: Option<Slideshow>,
 }
 
 #[derive(Parser, Debug)]
@@ -53,7 +105,7 @@ struct Parameters {
     #[arg(
         long,
         default_value_t = false,
-        conflicts_with_all=["newest_first", "oldest_first", "alphabetical"],
+        conflicts_with_all=["newest_first", "oldest_first", "alphabetical", "longest_first", "shortest_first", "highest_rated"],
         help="randomise the order of the images",
     )]
     pub randomise: bool,
@@ -61,7 +113,7 @@ struct Parameters {
     #[arg(
         long,
         default_value_t = false,
-        conflicts_with_all=["randomise", "alphabetical", "oldest_first"],
+        conflicts_with_all=["randomise", "alphabetical", "oldest_first", "longest_first", "shortest_first", "highest_rated"],
         help="sort the images by date, newest first",
         alias="date",
     )]
@@ -70,7 +122,7 @@ struct Parameters {
     #[arg(
         long,
         default_value_t = false,
-        conflicts_with_all=["randomise", "alphabetical", "newest_first"],
+        conflicts_with_all=["randomise", "alphabetical", "newest_first", "longest_first", "shortest_first", "highest_rated"],
         help="sort the images by date, oldest first",
     )]
     pub oldest_first: bool,
@@ -78,11 +130,42 @@ struct Parameters {
     #[arg(
         long,
         default_value_t = false,
-        conflicts_with_all=["newest_first", "oldest_first", "randomise"],
+        conflicts_with_all=["newest_first", "oldest_first", "randomise", "longest_first", "shortest_first", "highest_rated"],
         help="sort the images by name A-Z",
     )]
     pub alphabetical: bool,
 
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all=["newest_first", "oldest_first", "randomise", "alphabetical", "shortest_first", "highest_rated"],
+        help="sort by how long videos and animations play for, longest first",
+    )]
+    pub longest_first: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all=["newest_first", "oldest_first", "randomise", "alphabetical", "longest_first", "highest_rated"],
+        help="sort by how long videos and animations play for, shortest first",
+    )]
+    pub shortest_first: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all=["newest_first", "oldest_first", "randomise", "alphabetical", "longest_first", "shortest_first"],
+        help="sort by the star rating from xmp sidecars or the files themselves, highest first",
+    )]
+    pub highest_rated: bool,
+
+    #[arg(
+        long,
+        default_value_t = GroupBy::None,
+        help = "split the images into sections with a header for each folder, day or month",
+    )]
+    pub group_by: GroupBy,
+
     #[arg(
         short,
         long,
@@ -99,16 +182,103 @@ struct Parameters {
     )]
     pub filter: FilterParameter,
 
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        help = "only show files matching a filter expression, E.G. \"type:video min-width:1920 date:2023..2024\". See the readme for the full list"
+    )]
+    pub filter_expr: Option<FilterExpr>,
+
+    #[command(flatten)]
+    pub shape: ShapeFilter,
+
     #[arg(
         index = 1,
         required = true,
-        help = "file system path to the images to host"
+        num_args = 1..,
+        help = "file system paths to the images to host, folders or single files. Everything found is shown together"
     )]
-    pub path: String,
+    pub path: Vec<String>,
 
     #[arg(long, default_value_t = false, help = "recurse down directories")]
     pub recursive: bool,
 
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Also check files with no extension, showing them if their contents are a supported type"
+    )]
+    pub include_extensionless: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Show hidden files and folders. macOS ._ files are always left out"
+    )]
+    pub include_hidden: bool,
+
+    #[arg(
+        long,
+        help = "Leave out files and folders matching this gitignore style pattern. Can be given more than once"
+    )]
+    pub exclude: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Only show files matching this gitignore style pattern. Can be given more than once"
+    )]
+    pub include: Vec<String>,
+
+    #[arg(
+        long,
+        requires = "recursive",
+        help = "How many levels of sub folders to go down when recursing"
+    )]
+    pub max_depth: Option<usize>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Go into folders that are symlinks when recursing"
+    )]
+    pub follow_symlinks: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Don't go into folders on a different file system when recursing, like find -xdev"
+    )]
+    pub one_file_system: bool,
+
+    #[arg(
+        long,
+        help = "Keep favourites, tags and albums in this folder instead of a .picture_browser folder in each path"
+    )]
+    pub data_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Allow ratings set in the page to be saved to .xmp sidecar files next to the images"
+    )]
+    pub write_xmp: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Allow files to be deleted, moved and renamed from the page. Without it nothing is ever changed"
+    )]
+    pub allow_write: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = TrashKind::Folder,
+        requires = "allow_write",
+        help = "Where deleted files go, a .trash folder in the path or the desktop's trash"
+    )]
+    pub trash: TrashKind,
+
     #[arg(
         long,
         default_value_t = 8,
@@ -131,12 +301,15 @@ struct Parameters {
     pub no_browser: bool,
 }
 
-#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub enum FilterParameter {
+    #[default]
     None,
     Video,
     Images,
     Gif,
+    Animated,
+    Static,
 }
 
 impl std::fmt::Display for FilterParameter {
@@ -153,14 +326,44 @@ async fn main() {
     env_logger::init_from_env(Env::default().default_filter_or("info"));
     let args = Parameters::parse();
 
-    let images: Vec<ImageInfo> = find_files(&args.path, args.filter, args.recursive);
+    let scan = ScanOptions {
+        filter: args.filter,
+        filter_expr: args
+            .filter_expr
+            .clone()
+            .unwrap_or_default()
+            .and(args.shape.to_expr()),
+        recursive: args.recursive,
+        include_extensionless: args.include_extensionless,
+        include_hidden: args.include_hidden,
+        excludes: args.exclude.clone(),
+        includes: args.include.clone(),
+        max_depth: args.max_depth,
+        follow_symlinks: args.follow_symlinks,
+        one_file_system: args.one_file_system,
+    };
+
+    // Check the patterns up front so a typo doesn't just look like an empty folder.
+    for path in &args.path {
+        if let Err(e) = IgnoreRules::new(Path::new(path), &scan.excludes, &scan.includes) {
+            warn!("{}", e);
+            return;
+        }
+    }
+
+    let mut annotations = Annotations::load(&args.path, args.data_dir.as_deref());
+
+    let (images, scan_report) = find_files(&args.path, &scan, &mut annotations);
     info!("Found {} files", images.len());
+    if !scan_report.problems.is_empty() {
+        warn!("{} files could not be read", scan_report.problems.len());
+    }
     if images.is_empty() {
         info!("Nothing found to display");
         return;
     }
     let sort_by = SortBy::from_parameters(&args);
-    let sorted_images = sort(&sort_by, &images);
+    let sorted_images = sort(&sort_by, args.group_by, &images);
 
     // Should only be able to return none if the list is empty, and we've already short circuited if images is empty.
     let tree_root = tree::TreeNode::tree_from_images(&sorted_images).unwrap();
@@ -168,15 +371,25 @@ async fn main() {
     let templates = create_templates("./");
 
     let data = AppData {
-        target_path: args.path.clone(),
+        target_paths: args.path.clone(),
         images: sorted_images,
-        tree: tree_root,
+        tree: Some(tree_root),
         sort: sort_by,
-        filter: args.filter,
-        recursive: args.recursive,
+        group_by: args.group_by,
+        scan,
+        scan_report,
+        annotations,
         templates,
         background: args.background.clone(),
-        hot_reload: args.hot_reload.clone(),
+        hot_reload: args.hot_reload,
+        // Poster frames need ffmpeg, without it the page just waits for the video to load.
+        posters: which::which("ffmpeg").is_ok(),
+        write_xmp: args.write_xmp,
+        file_actions: args
+            .allow_write
+            .then(|| FileActions::new(&args.path, args.trash)),
+        hashes: HashCache::default(),
+        slideshow: None,
     };
 
     let web_data = web::Data::new(RwLock::new(data));
@@ -201,7 +414,68 @@ async fn main() {
             .route("/favicon.ico", web::get().to(favicon))
             .route("/refresh", web::get().to(refresh))
             .route("/tree/{tree_path}", web::get().to(tree_path))
+            .route("/view/{image_name}", web::get().to(view))
+            .route("/sheet", web::get().to(sheet))
+            .route("/slideshow", web::get().to(slideshow_page))
             .route("/img/{image_name}", web::get().to(image_request))
+            .route("/poster/{image_name}", web::get().to(poster_request))
+            .route("/thumb/{image_name}", web::get().to(thumbnail_request))
+            .route("/api/images", web::get().to(images_json))
+            .route("/api/folders", web::get().to(folders_json))
+            .route("/api/timeline", web::get().to(timeline_json))
+            .route("/api/search", web::get().to(search_json))
+            .route("/api/scan-report", web::get().to(scan_report_json))
+            .route("/api/tags", web::get().to(tags_json))
+            .route("/api/albums", web::get().to(albums_json))
+            .route("/api/favourites", web::get().to(favourites_json))
+            .route("/api/file-actions", web::get().to(file_actions_json))
+            .route("/api/cull", web::get().to(cull_json))
+            .route("/api/duplicates", web::get().to(duplicates_json))
+            .route("/api/contact-sheet", web::get().to(contact_sheet_json))
+            .route("/api/slideshow", web::get().to(slideshow_json))
+            // Changes are never a POST so another site can't send one from a form, put and delete need a cors
+            // preflight.
+            .route("/api/favourites/{image_name}", web::put().to(add_favourite))
+            .route(
+                "/api/favourites/{image_name}",
+                web::delete().to(remove_favourite),
+            )
+            .route(
+                "/api/images/{image_name}/tags/{name}",
+                web::put().to(add_tag),
+            )
+            .route(
+                "/api/images/{image_name}/tags/{name}",
+                web::delete().to(remove_tag),
+            )
+            .route(
+                "/api/images/{image_name}/albums/{name}",
+                web::put().to(add_to_album),
+            )
+            .route(
+                "/api/images/{image_name}/albums/{name}",
+                web::delete().to(remove_from_album),
+            )
+            .route(
+                "/api/images/{image_name}/rating/{rating}",
+                web::put().to(set_rating),
+            )
+            .route(
+                "/api/images/{image_name}/cull/{decision}",
+                web::put().to(decide),
+            )
+            .route("/api/images/{image_name}/cull", web::delete().to(undecide))
+            .route("/api/cull/apply", web::put().to(apply_culling))
+            .route("/api/images/{image_name}", web::delete().to(trash_image))
+            .route("/api/images/{image_name}/move", web::put().to(move_image))
+            .route(
+                "/api/images/{image_name}/rename",
+                web::put().to(rename_image),
+            )
+            .route("/api/slideshow", web::put().to(start_slideshow))
+            .route("/api/slideshow", web::delete().to(stop_slideshow))
+            .route("/api/slideshow/next", web::put().to(next_slide))
+            .route("/api/slideshow/previous", web::put().to(previous_slide))
     })
     .workers(args.workers)
     .bind((bind, args.port))
@@ -223,21 +497,415 @@ async fn main() {
     set.join_next().await;
 }
 
-async fn index(data: web::Data<RwLock<AppData>>) -> Result<impl Responder> {
+#[derive(Deserialize, Debug)]
+struct IndexQuery {
+    /// Only show the images found under this one of the command line paths.
+    root: Option<String>,
+    /// Only show the images in this folder and the folders below it, relative to the root.
+    folder: Option<String>,
+    /// A filter expression, on top of any given on the command line.
+    filter: Option<String>,
+    /// Only show the images in this album, from wherever they are.
+    album: Option<String>,
+    /// Only show the images that have copies, next to each other.
+    duplicates: Option<Method>,
+    /// How different duplicates found by their perceptual hashes can be, out of 64.
+    #[serde(default, deserialize_with = "filter_expr::from_str_option")]
+    distance: Option<u32>,
+    #[serde(flatten)]
+    shape: ShapeFilter,
+}
+
+impl IndexQuery {
+    fn is_scoped(&self) -> bool {
+        self.root.is_some()
+            || self.folder.is_some()
+            || self.filter.is_some()
+            || self.album.is_some()
+            || self.duplicates.is_some()
+            || !self.shape.to_expr().is_empty()
+    }
+
+    fn includes(&self, image: &ImageInfo) -> bool {
+        self.root.as_ref().is_none_or(|root| &image.root == root)
+            && self
+                .folder
+                .as_ref()
+                .is_none_or(|folder| in_folder(image, folder))
+            && self
+                .album
+                .as_ref()
+                .is_none_or(|album| image.albums.contains(album))
+    }
+}
+
+async fn index(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<IndexQuery>,
+) -> Result<impl Responder> {
+    if let Some(method) = query.duplicates {
+        hash_files(&data, method).await?;
+    }
     let data = data.read().map_err(|_e| Error::Lock())?;
 
     #[cfg(feature = "post")]
-    send_message(&data.target_path)?;
+    send_message(&data.target_paths.join(", "))?;
+
+    let images = scoped_images(&data, &query)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(generate_index(&data, &query, &images)))
+}
+
+/// One file on its own, with links to the ones either side of it in the same order and with the same query as the
+/// page.
+async fn view(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<IndexQuery>,
+    req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Some(method) = query.duplicates {
+        hash_files(&data, method).await?;
+    }
+    let url = req.match_info().query("image_name");
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let images = scoped_images(&data, &query)?;
+    let position = images
+        .iter()
+        .position(|i| i.url == url)
+        .ok_or_else(|| Error::NotFound(url.to_string()))?;
+
+    let mut context = Context::new();
+    context.insert("image", &images[position]);
+    context.insert("previous", &position.checked_sub(1).map(|i| &images[i].url));
+    context.insert("next", &images.get(position + 1).map(|i| &i.url));
+    context.insert("position", &(position + 1));
+    context.insert("total", &images.len());
+    let query_string = req.query_string();
+    context.insert(
+        "query",
+        &if query_string.is_empty() {
+            String::new()
+        } else {
+            format!("?{query_string}")
+        },
+    );
+    context.insert("background", &data.background);
+    context.insert("posters", &data.posters);
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(generate_index(
-            &data.templates,
-            &data.target_path,
-            &data.images,
-            &data.background,
-            data.hot_reload,
-        )))
+        .body(render(&data, "view.html", &context)))
+}
+
+/// The images picked out by the query. Showing part of the list needs its own headers and heights, as the first image
+/// of a group may not be in it.
+fn scoped_images<'a>(data: &'a AppData, query: &IndexQuery) -> Result<Cow<'a, [ImageInfo]>> {
+    if !query.is_scoped() {
+        return Ok(Cow::Borrowed(&data.images));
+    }
+
+    let filter = query
+        .filter
+        .as_deref()
+        .unwrap_or_default()
+        .parse::<FilterExpr>()?
+        .and(query.shape.to_expr());
+    let mut scoped: Vec<ImageInfo> = data
+        .images
+        .iter()
+        .filter(|i| query.includes(i) && filter.matches(i))
+        .cloned()
+        .collect();
+
+    let Some(method) = query.duplicates else {
+        layout(&mut scoped, data.group_by);
+        return Ok(Cow::Owned(scoped));
+    };
+    // Each group of copies gets its own header, in place of the usual grouping.
+    let groups = find_duplicates(
+        &scoped,
+        &data.hashes,
+        method,
+        query.distance.unwrap_or(DEFAULT_DISTANCE),
+    );
+    let mut duplicates = Vec::new();
+    for group in groups {
+        let header = format!(
+            "{} {}",
+            group.files.len(),
+            if group.exact { "copies" } else { "alike" }
+        );
+        let mut files = group.files.into_iter().map(|f| f.image);
+        duplicates.extend(files.next().map(|mut first| {
+            first.header = Some(header);
+            first
+        }));
+        duplicates.extend(files.map(|mut image| {
+            image.header = None;
+            image
+        }));
+    }
+    set_heights(&mut duplicates);
+    Ok(Cow::Owned(duplicates))
+}
+
+/// Hashes any of the files that haven't been yet, without holding the lock while it happens so the rest of the page
+/// carries on working.
+async fn hash_files(data: &web::Data<RwLock<AppData>>, method: Method) -> Result<()> {
+    let candidates = {
+        let data = data.read().map_err(|_e| Error::Lock())?;
+        data.hashes.candidates(&data.images)
+    };
+
+    // Even checking which files have changed means reading every one's metadata.
+    let hashes = web::block(move || duplicates::run(duplicates::jobs(candidates, method))).await?;
+    if hashes.is_empty() {
+        return Ok(());
+    }
+    data.write()
+        .map_err(|_e| Error::Lock())?
+        .hashes
+        .insert(hashes);
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+struct DuplicatesQuery {
+    #[serde(default)]
+    method: Method,
+    #[serde(default, deserialize_with = "filter_expr::from_str_option")]
+    distance: Option<u32>,
+    #[serde(flatten)]
+    scope: IndexQuery,
+}
+
+/// The groups of files that are copies of each other. The first request hashes every file, which can take a while.
+async fn duplicates_json(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<DuplicatesQuery>,
+) -> Result<HttpResponse> {
+    hash_files(&data, query.method).await?;
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let images = scoped_images(&data, &query.scope)?;
+
+    Ok(HttpResponse::Ok().json(find_duplicates(
+        &images,
+        &data.hashes,
+        query.method,
+        query.distance.unwrap_or(DEFAULT_DISTANCE),
+    )))
+}
+
+#[derive(Deserialize, Debug)]
+struct ContactSheetQuery {
+    /// The size of each square in css pixels.
+    #[serde(default, deserialize_with = "filter_expr::from_str_option")]
+    tile: Option<u32>,
+    /// The width of the page in css pixels, which decides how many fit across.
+    #[serde(default, deserialize_with = "filter_expr::from_str_option")]
+    width: Option<u32>,
+    #[serde(flatten)]
+    scope: IndexQuery,
+}
+
+/// Where each file goes in the contact sheet, in the same order as the page.
+async fn contact_sheet_json(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<ContactSheetQuery>,
+) -> Result<HttpResponse> {
+    if let Some(method) = query.scope.duplicates {
+        hash_files(&data, method).await?;
+    }
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let images = scoped_images(&data, &query.scope)?;
+
+    Ok(HttpResponse::Ok().json(contact_sheet(
+        &images,
+        query.tile.unwrap_or(DEFAULT_TILE),
+        query.width.unwrap_or(DEFAULT_SHEET_WIDTH),
+    )))
+}
+
+/// The contact sheet page, which gets its layout from `/api/contact-sheet` once it knows how wide it is.
+async fn sheet(data: web::Data<RwLock<AppData>>) -> Result<impl Responder> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+
+    let mut context = Context::new();
+    context.insert("background", &data.background);
+    context.insert("default_tile", &DEFAULT_TILE);
+    context.insert("min_tile", &MIN_TILE);
+    context.insert("max_tile", &MAX_TILE);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render(&data, "sheet.html", &context)))
+}
+
+#[derive(Deserialize, Debug)]
+struct SlideshowQuery {
+    /// How long each still image stays up.
+    #[serde(default, deserialize_with = "filter_expr::from_str_option")]
+    seconds: Option<f64>,
+    #[serde(default, deserialize_with = "filter_expr::from_str_option")]
+    shuffle: Option<bool>,
+    #[serde(
+        default,
+        rename = "loop",
+        deserialize_with = "filter_expr::from_str_option"
+    )]
+    looping: Option<bool>,
+    #[serde(default)]
+    transition: Transition,
+    #[serde(flatten)]
+    scope: IndexQuery,
+}
+
+#[derive(Serialize, Debug)]
+struct SlideshowState<'a> {
+    /// The server's clock, so a screen can tell how far off its own is.
+    now_ms: u64,
+    /// None once a show that doesn't loop has finished.
+    position: Option<Position>,
+    #[serde(flatten)]
+    show: &'a Slideshow,
+}
+
+fn slideshow_response(show: &Slideshow) -> HttpResponse {
+    let now_ms = now_ms();
+    HttpResponse::Ok().json(SlideshowState {
+        now_ms,
+        position: show.position(now_ms),
+        show,
+    })
+}
+
+/// The page that plays the show. Any number of screens can have it open, they all show the same slide.
+async fn slideshow_page(data: web::Data<RwLock<AppData>>) -> Result<impl Responder> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+
+    let mut context = Context::new();
+    context.insert("default_seconds", &DEFAULT_IMAGE_SECONDS);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render(&data, "slideshow.html", &context)))
+}
+
+async fn slideshow_json(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let show = data
+        .slideshow
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("no slideshow has been started".to_string()))?;
+
+    Ok(slideshow_response(show))
+}
+
+/// Starts a new show of the images picked out by the same query as the page, in the same order unless shuffled.
+/// Takes over from any show already running, on every screen.
+async fn start_slideshow(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<SlideshowQuery>,
+) -> Result<HttpResponse> {
+    if let Some(method) = query.scope.duplicates {
+        hash_files(&data, method).await?;
+    }
+    let options = SlideshowOptions {
+        image_ms: image_ms(query.seconds.unwrap_or(DEFAULT_IMAGE_SECONDS))?,
+        shuffle: query.shuffle.unwrap_or(false),
+        looping: query.looping.unwrap_or(true),
+        transition: query.transition,
+    };
+    let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let version = data.slideshow.as_ref().map_or(1, |show| show.version + 1);
+    let show = Slideshow::new(
+        &scoped_images(&data, &query.scope)?,
+        &options,
+        now_ms(),
+        version,
+    )
+    .ok_or_else(|| Error::NotFound("nothing to show".to_string()))?;
+    info!("Starting a slideshow of {} files", show.slides.len());
+
+    let response = slideshow_response(&show);
+    data.slideshow = Some(show);
+    Ok(response)
+}
+
+async fn stop_slideshow(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    data.write().map_err(|_e| Error::Lock())?.slideshow = None;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn next_slide(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    skip_slide(&data, true)
+}
+
+async fn previous_slide(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    skip_slide(&data, false)
+}
+
+fn skip_slide(data: &RwLock<AppData>, forward: bool) -> Result<HttpResponse> {
+    let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let show = data
+        .slideshow
+        .as_mut()
+        .ok_or_else(|| Error::NotFound("no slideshow has been started".to_string()))?;
+    show.skip(now_ms(), forward);
+
+    Ok(slideshow_response(show))
+}
+
+#[derive(Deserialize, Debug)]
+struct TimelineQuery {
+    #[serde(default)]
+    bucket: Bucket,
+    /// The same root and folder as the page, so the offsets match it.
+    #[serde(flatten)]
+    scope: IndexQuery,
+}
+
+async fn timeline_json(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<TimelineQuery>,
+) -> Result<HttpResponse> {
+    if let Some(method) = query.scope.duplicates {
+        hash_files(&data, method).await?;
+    }
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let images = scoped_images(&data, &query.scope)?;
+
+    Ok(HttpResponse::Ok().json(timeline(&images, query.bucket)))
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchQuery {
+    q: String,
+    #[serde(default)]
+    mode: SearchMode,
+    #[serde(default = "default_search_limit")]
+    limit: usize,
+    #[serde(flatten)]
+    scope: IndexQuery,
+}
+
+fn default_search_limit() -> usize {
+    50
+}
+
+async fn search_json(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse> {
+    if let Some(method) = query.scope.duplicates {
+        hash_files(&data, method).await?;
+    }
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let images = scoped_images(&data, &query.scope)?;
+
+    Ok(HttpResponse::Ok().json(search(&images, &query.q, query.mode, query.limit)?))
 }
 
 async fn image_request(
@@ -245,36 +913,557 @@ async fn image_request(
     req: HttpRequest,
 ) -> Result<impl Responder> {
     let path = req.match_info().query("image_name");
+    let (source, media_type) = {
+        let data = data.read().map_err(|_e| Error::Lock())?;
+        let img = find_image(&data.images, path)?;
+        (img.path.clone(), img.media_type)
+    };
+    original_response(source, media_type, &req).await
+}
+
+/// The file itself, or a png of it for types the browser can't show. Takes the path rather than the image so it's
+/// called after the lock has gone, as converting decodes the whole file and is done off the worker thread.
+async fn original_response(
+    source: PathBuf,
+    media_type: MediaType,
+    req: &HttpRequest,
+) -> Result<HttpResponse> {
+    let media_file = if media_type.needs_conversion() {
+        let converted = web::block(move || convert::converted_path(&source)).await??;
+        MediaFile::open(&converted, "image/png")?
+    } else {
+        // The type is set from the file contents as the extension might be wrong or missing.
+        MediaFile::open(&source, media_type.mime())?
+    };
+
+    let mut response = media_file.into_response(req);
+    if media_type == MediaType::Svg {
+        // svg files can contain scripts, these must never run with access to the gallery page.
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(SVG_CONTENT_SECURITY_POLICY),
+        );
+        headers.insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        );
+    }
+    Ok(response)
+}
+
+async fn poster_request(
+    data: web::Data<RwLock<AppData>>,
+    req: HttpRequest,
+) -> Result<impl Responder> {
+    let path = req.match_info().query("image_name");
+    let (source, duration_ms) = {
+        let data = data.read().map_err(|_e| Error::Lock())?;
+        let img = find_image(&data.images, path)?;
+        if !img.is_video {
+            return Err(Error::NotFound(path.to_string()).into());
+        }
+        (img.path.clone(), img.duration_ms)
+    };
+
+    // ffmpeg can take a while, so it's run off the worker thread and without the lock.
+    let poster = web::block(move || convert::poster_path(&source, duration_ms)).await??;
+    Ok(MediaFile::open(&poster, "image/jpeg")?.into_response(&req))
+}
+
+#[derive(Deserialize, Debug)]
+struct ThumbnailQuery {
+    size: Option<u32>,
+}
+
+/// A small jpeg of an image, or of a video's poster frame. The size is rounded up so only a few sizes are ever cached.
+/// svg and ico files can't be shrunk so are sent as they are.
+async fn thumbnail_request(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<ThumbnailQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let path = req.match_info().query("image_name");
+    let size = thumbnail_size(query.size.unwrap_or(DEFAULT_TILE));
+    let (source, media_type, is_video, duration_ms) = {
+        let data = data.read().map_err(|_e| Error::Lock())?;
+        let img = find_image(&data.images, path)?;
+        (
+            img.path.clone(),
+            img.media_type,
+            img.is_video,
+            img.duration_ms,
+        )
+    };
+    if matches!(media_type, MediaType::Svg | MediaType::Ico) {
+        return original_response(source, media_type, &req).await;
+    }
+
+    // Decoding a large photo takes a while, so it's done off the worker thread and without the lock.
+    let thumbnail =
+        web::block(move || convert::thumbnail_path(&source, is_video, duration_ms, size)).await??;
+    Ok(MediaFile::open(&thumbnail, "image/jpeg")?.into_response(&req))
+}
+
+async fn images_json(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<IndexQuery>,
+) -> Result<HttpResponse> {
+    if let Some(method) = query.duplicates {
+        hash_files(&data, method).await?;
+    }
     let data = data.read().map_err(|_e| Error::Lock())?;
-    for img in data.images.iter() {
-        if img.url.as_str() == path {
-            let named_file = NamedFile::open(&img.source)?;
-            return Ok(named_file.into_response(&req));
+    let images = scoped_images(&data, &query)?;
+
+    Ok(HttpResponse::Ok().json(&images))
+}
+
+async fn folders_json(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+
+    Ok(HttpResponse::Ok().json(folders(&data.target_paths, &data.images, data.posters)))
+}
+
+async fn scan_report_json(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+
+    Ok(HttpResponse::Ok().json(&data.scan_report))
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ListFormat {
+    #[default]
+    Json,
+    /// One full path per line, to pipe into other tools.
+    Text,
+}
+
+#[derive(Deserialize, Debug)]
+struct FavouritesQuery {
+    #[serde(default)]
+    format: ListFormat,
+}
+
+async fn favourites_json(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<FavouritesQuery>,
+) -> Result<HttpResponse> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let favourites = data.annotations.favourites.list();
+
+    Ok(match query.format {
+        ListFormat::Json => HttpResponse::Ok().json(favourites),
+        ListFormat::Text => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(
+                favourites
+                    .iter()
+                    .map(|f| format!("{}\n", f.path))
+                    .collect::<String>(),
+            ),
+    })
+}
+
+async fn add_favourite(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
+    set_favourite(&data, req.match_info().query("image_name"), true)
+}
+
+async fn remove_favourite(
+    data: web::Data<RwLock<AppData>>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    set_favourite(&data, req.match_info().query("image_name"), false)
+}
+
+fn set_favourite(data: &RwLock<AppData>, url: &str, favourite: bool) -> Result<HttpResponse> {
+    let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let data = &mut *data;
+    let image = find_image_mut(&mut data.images, url)?;
+
+    let annotations = &mut data.annotations;
+    annotations
+        .favourites
+        .set(&annotations.store, image, favourite)?;
+    image.favourite = favourite;
+    update_tree(&mut data.tree, image);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn tags_json(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+
+    Ok(HttpResponse::Ok().json(data.annotations.tags.tags()))
+}
+
+async fn albums_json(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+
+    Ok(HttpResponse::Ok().json(data.annotations.tags.albums()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Label {
+    Tag,
+    Album,
+}
+
+async fn add_tag(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
+    set_label(&data, &req, Label::Tag, true)
+}
+
+async fn remove_tag(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
+    set_label(&data, &req, Label::Tag, false)
+}
+
+async fn add_to_album(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
+    set_label(&data, &req, Label::Album, true)
+}
+
+async fn remove_from_album(
+    data: web::Data<RwLock<AppData>>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    set_label(&data, &req, Label::Album, false)
+}
+
+/// Responds with the image, so the page can show its tags and albums as they now are.
+fn set_label(
+    data: &RwLock<AppData>,
+    req: &HttpRequest,
+    label: Label,
+    on: bool,
+) -> Result<HttpResponse> {
+    let url = req.match_info().query("image_name");
+    let name = req.match_info().query("name");
+    let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let data = &mut *data;
+    let image = find_image_mut(&mut data.images, url)?;
+
+    let annotations = &mut data.annotations;
+    match label {
+        Label::Tag => annotations
+            .tags
+            .set_tag(&annotations.store, image, name, on)?,
+        Label::Album => annotations
+            .tags
+            .set_album(&annotations.store, image, name, on)?,
+    }
+    update_tree(&mut data.tree, image);
+
+    Ok(HttpResponse::Ok().json(&*image))
+}
+
+async fn set_rating(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
+    let url = req.match_info().query("image_name");
+    let rating = req.match_info().query("rating");
+    let rating: i8 = rating
+        .parse()
+        .map_err(|_| Error::InvalidRating(rating.to_string()))?;
+    let path = {
+        let data = data.read().map_err(|_e| Error::Lock())?;
+        if !data.write_xmp {
+            return Err(
+                Error::ReadOnly("start with --write-xmp to save ratings".to_string()).into(),
+            );
+        }
+        find_image(&data.images, url)?.path.clone()
+    };
+
+    let sidecar = web::block(move || xmp::write_rating(&path, rating)).await??;
+
+    let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let data = &mut *data;
+    let image = find_image_mut(&mut data.images, url)?;
+    info!("Rated {} {} in {:?}", image.source, rating, sidecar);
+    image.rating = Some(rating);
+    update_tree(&mut data.tree, image);
+
+    Ok(HttpResponse::Ok().json(&*image))
+}
+
+async fn decide(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
+    let decision: Decision = req.match_info().query("decision").parse()?;
+    set_decision(&data, req.match_info().query("image_name"), Some(decision))
+}
+
+/// Forgets what was decided, for undo.
+async fn undecide(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
+    set_decision(&data, req.match_info().query("image_name"), None)
+}
+
+fn set_decision(
+    data: &RwLock<AppData>,
+    url: &str,
+    decision: Option<Decision>,
+) -> Result<HttpResponse> {
+    let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let data = &mut *data;
+    let image = find_image_mut(&mut data.images, url)?;
+
+    let annotations = &mut data.annotations;
+    annotations
+        .culling
+        .decide(&annotations.store, image, decision)?;
+    update_tree(&mut data.tree, image);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Debug)]
+struct CullQuery {
+    #[serde(default)]
+    format: ListFormat,
+    #[serde(flatten)]
+    scope: IndexQuery,
+}
+
+/// How many have been kept and rejected, or with `?format=text` just the rejected paths.
+async fn cull_json(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<CullQuery>,
+) -> Result<HttpResponse> {
+    if let Some(method) = query.scope.duplicates {
+        hash_files(&data, method).await?;
+    }
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let images = scoped_images(&data, &query.scope)?;
+    let summary = Culling::summary(&images);
+
+    Ok(match query.format {
+        ListFormat::Json => HttpResponse::Ok().json(summary),
+        ListFormat::Text => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(
+                summary
+                    .rejects
+                    .iter()
+                    .map(|path| format!("{path}\n"))
+                    .collect::<String>(),
+            ),
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct ApplyQuery {
+    /// Where to move the rejected files, under their roots. Without one they are only listed.
+    folder: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct ApplyResult {
+    moved: Vec<Applied>,
+    /// The `rejects.txt` files written.
+    lists: Vec<String>,
+}
+
+/// Finishes culling, either moving the rejected files into a folder, which needs `--allow-write`, or writing a list
+/// of them next to the favourites.
+async fn apply_culling(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<ApplyQuery>,
+) -> Result<HttpResponse> {
+    let mut result = ApplyResult::default();
+    match &query.folder {
+        Some(folder) => {
+            let (actions, moves) = {
+                let data = data.read().map_err(|_e| Error::Lock())?;
+                let actions = file_actions(data.file_actions.as_ref())?.clone();
+                (actions, plan_rejects(&data.images, folder)?)
+            };
+
+            let (moved, failed) = web::block(move || move_rejects(&actions, moves)).await?;
+
+            let mut data = data.write().map_err(|_e| Error::Lock())?;
+            let data = &mut *data;
+            let recorded = record_moves(&mut data.annotations, &mut data.images, moved);
+            // Some may have moved before one failed, the list has to match the disk either way.
+            relayout(data);
+            failed?;
+            result.moved = recorded?;
+        }
+        None => {
+            let data = data.read().map_err(|_e| Error::Lock())?;
+            result.lists = Culling::write_lists(&data.annotations.store, &data.images)?;
         }
     }
-    panic!("not found {}", path);
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Serialize, Debug)]
+struct FileActionsInfo<'a> {
+    allowed: bool,
+    trash: Option<TrashKind>,
+    /// The paths that can't be changed and why.
+    read_only: Option<&'a BTreeMap<String, String>>,
+}
+
+/// Lets the page know whether to offer deleting, moving and renaming.
+async fn file_actions_json(data: web::Data<RwLock<AppData>>) -> Result<HttpResponse> {
+    let data = data.read().map_err(|_e| Error::Lock())?;
+    let actions = data.file_actions.as_ref();
+
+    Ok(HttpResponse::Ok().json(FileActionsInfo {
+        allowed: actions.is_some(),
+        trash: actions.map(|a| a.trash),
+        read_only: actions.map(|a| &a.read_only),
+    }))
+}
+
+fn file_actions(actions: Option<&FileActions>) -> Result<&FileActions, Error> {
+    actions.ok_or_else(|| Error::ReadOnly("start with --allow-write to change files".to_string()))
+}
+
+/// Moves the file to the trash and takes it out of the list.
+async fn trash_image(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
+    let url = req.match_info().query("image_name");
+    let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let index = data
+        .images
+        .iter()
+        .position(|img| img.url == url)
+        .ok_or_else(|| Error::NotFound(url.to_string()))?;
+
+    file_actions(data.file_actions.as_ref())?.trash(&data.images[index])?;
+    data.images.remove(index);
+    relayout(&mut data);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Debug)]
+struct MoveQuery {
+    /// Relative to the root, made if it isn't there. Empty for the root itself.
+    folder: String,
+}
+
+async fn move_image(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<MoveQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    relocate_image(
+        &data,
+        req.match_info().query("image_name"),
+        |actions, image| actions.move_to(image, &query.folder),
+    )
+}
+
+#[derive(Deserialize, Debug)]
+struct RenameQuery {
+    name: String,
+}
+
+async fn rename_image(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<RenameQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    relocate_image(
+        &data,
+        req.match_info().query("image_name"),
+        |actions, image| actions.rename(image, &query.name),
+    )
+}
+
+/// Responds with the image at its new path. Its favourite, tags and albums go with it.
+fn relocate_image(
+    data: &RwLock<AppData>,
+    url: &str,
+    relocate: impl FnOnce(&FileActions, &mut ImageInfo) -> Result<String, Error>,
+) -> Result<HttpResponse> {
+    let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let data = &mut *data;
+    let actions = file_actions(data.file_actions.as_ref())?;
+    let image = find_image_mut(&mut data.images, url)?;
+
+    let old_path = relocate(actions, image)?;
+    if let Err(e) = data.annotations.moved(image, &old_path) {
+        warn!("Could not move what's kept about {}: {}", image.source, e);
+    }
+    let image = image.clone();
+    relayout(data);
+
+    Ok(HttpResponse::Ok().json(image))
+}
+
+/// After an image has gone or moved. The images are sorted again, as a new name or folder can change where they go,
+/// apart from when they are random.
+fn relayout(data: &mut AppData) {
+    if data.sort == SortBy::Randomise {
+        data.group_by.gather(&mut data.images, false);
+        layout(&mut data.images, data.group_by);
+    } else {
+        data.images = sort(&data.sort, data.group_by, &data.images);
+    }
+    data.tree = TreeNode::tree_from_images(&data.images);
+}
+
+/// After a change to an image that leaves it where it is, like marking it a favourite. Only its copy in the tree is
+/// replaced rather than building the tree again.
+fn update_tree(tree: &mut Option<TreeNode>, image: &ImageInfo) {
+    if let Some(tree) = tree {
+        tree.update_image(image);
+    }
+}
+
+fn find_image<'a>(images: &'a [ImageInfo], url: &str) -> Result<&'a ImageInfo, Error> {
+    images
+        .iter()
+        .find(|img| img.url == url)
+        .ok_or_else(|| Error::NotFound(url.to_string()))
+}
+
+fn find_image_mut<'a>(images: &'a mut [ImageInfo], url: &str) -> Result<&'a mut ImageInfo, Error> {
+    images
+        .iter_mut()
+        .find(|img| img.url == url)
+        .ok_or_else(|| Error::NotFound(url.to_string()))
 }
 
 async fn refresh(data: web::Data<RwLock<AppData>>) -> Result<()> {
     let mut data = data.write().map_err(|_e| Error::Lock())?;
+    let data = &mut *data;
 
     info!("Refreshing images from disk");
-    let images: Vec<ImageInfo> = find_files(&data.target_path, data.filter, data.recursive);
+    let (images, scan_report) = find_files(&data.target_paths, &data.scan, &mut data.annotations);
     info!("Found {} files", images.len());
     if images.is_empty() {
         info!("Nothing found to display");
     }
 
-    data.images = sort(&data.sort, &images);
+    data.images = sort(&data.sort, data.group_by, &images);
+    data.tree = TreeNode::tree_from_images(&data.images);
+    data.scan_report = scan_report;
 
     Ok(())
 }
 
-async fn tree_path(data: web::Data<RwLock<AppData>>, req: HttpRequest) -> Result<HttpResponse> {
-    let path = req.match_info().query("image_name");
+/// Takes the same query as the page, an album or a folder gets a tree of just its images.
+async fn tree_path(
+    data: web::Data<RwLock<AppData>>,
+    query: web::Query<IndexQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Some(method) = query.duplicates {
+        hash_files(&data, method).await?;
+    }
+    let path = req.match_info().query("tree_path");
     let data = data.read().map_err(|_e| Error::Lock())?;
 
-    Ok(HttpResponse::Ok().json(TreeNodeLayer::from(data.tree.path(path)?)))
+    if !query.is_scoped() {
+        let tree = data
+            .tree
+            .as_ref()
+            .ok_or_else(|| Error::NotFound("nothing to show".to_string()))?;
+        return Ok(HttpResponse::Ok().json(TreeNodeLayer::from(tree.path(path)?)));
+    }
+    let images = scoped_images(&data, &query)?;
+    let tree = TreeNode::tree_from_images(&images)
+        .ok_or_else(|| Error::NotFound("nothing to show".to_string()))?;
+    Ok(HttpResponse::Ok().json(TreeNodeLayer::from(tree.path(path)?)))
 }
 
 async fn favicon() -> Result<impl Responder> {
@@ -284,12 +1473,15 @@ async fn favicon() -> Result<impl Responder> {
         .body(BoxBody::new(icon_bytes.as_slice())))
 }
 
-fn sort(by: &SortBy, input: &Vec<ImageInfo>) -> Vec<ImageInfo> {
-    let mut result = input.clone();
+fn sort(by: &SortBy, group_by: GroupBy, input: &[ImageInfo]) -> Vec<ImageInfo> {
+    let mut result = input.to_vec();
     match by {
         SortBy::Alphabetical => result.sort_by(|a, b| a.source.cmp(&b.source)),
-        SortBy::DateNewestFirst => result.sort_by(|a, b| b.date.cmp(&a.date)),
-        SortBy::DateOldestFirst => result.sort_by(|a, b| a.date.cmp(&b.date)),
+        SortBy::DateNewestFirst => result.sort_by_key(|b| std::cmp::Reverse(b.date)),
+        SortBy::DateOldestFirst => result.sort_by_key(|a| a.date),
+        SortBy::LongestFirst => result.sort_by_key(|b| std::cmp::Reverse(b.duration_ms)),
+        SortBy::ShortestFirst => result.sort_by_key(|a| a.duration_ms),
+        SortBy::HighestRated => result.sort_by_key(|b| std::cmp::Reverse(b.rating.unwrap_or(0))),
         SortBy::Randomise => {
             let mut rng = rand::rng();
             result.shuffle(&mut rng);
@@ -298,21 +1490,33 @@ fn sort(by: &SortBy, input: &Vec<ImageInfo>) -> Vec<ImageInfo> {
         SortBy::None => {}
     }
 
-    // Re-calculate the height before and after fields.
+    group_by.gather(&mut result, *by == SortBy::DateNewestFirst);
+    layout(&mut result, group_by);
+
+    result
+}
+
+/// Puts in the group headers then re-calculates the height before and after fields. Needed whenever the list of
+/// images changes, including when only part of it is being shown.
+fn layout(images: &mut [ImageInfo], group_by: GroupBy) {
+    group_by.set_headers(images);
+    set_heights(images);
+}
+
+/// The height before and after fields, from the images and their headers.
+fn set_heights(images: &mut [ImageInfo]) {
     let mut running_total: u64 = 0;
-    for e in result.iter_mut() {
+    for e in images.iter_mut() {
         e.height_before = running_total;
-        running_total += e.height + IMAGE_OFFSET;
+        running_total += e.layout_height();
     }
 
     let total = running_total;
     running_total = 0;
-    for e in result.iter_mut() {
-        running_total += e.height + IMAGE_OFFSET;
+    for e in images.iter_mut() {
+        running_total += e.layout_height();
         e.height_after = total - running_total;
     }
-
-    result
 }
 
 #[derive(PartialEq)]
@@ -320,6 +1524,9 @@ enum SortBy {
     Alphabetical,
     DateNewestFirst,
     DateOldestFirst,
+    LongestFirst,
+    ShortestFirst,
+    HighestRated,
     Randomise,
     None,
 }
@@ -334,6 +1541,12 @@ impl SortBy {
             SortBy::Randomise
         } else if args.oldest_first {
             SortBy::DateOldestFirst
+        } else if args.longest_first {
+            SortBy::LongestFirst
+        } else if args.shortest_first {
+            SortBy::ShortestFirst
+        } else if args.highest_rated {
+            SortBy::HighestRated
         } else {
             SortBy::None
         }
@@ -344,43 +1557,832 @@ fn create_templates(path: &str) -> Tera {
     let mut tera = Tera::default();
     tera.autoescape_on(vec![]);
 
-    let index_file_path = Path::new(path).join("index.html");
-    if index_file_path.exists() {
-        if let Err(e) = tera.add_template_file(index_file_path, Some("index.html")) {
-            warn!("Parsing error(s): {}", e);
-            panic!("Could not parse custom template. Aborting");
-        }
-    } else {
-        info!("No custom index.html found in the current directory so using default template");
-        if let Err(e) = tera.add_raw_template("index.html", DEFAULT_INDEX) {
-            warn!("Parsing error(s): {}", e);
-            panic!("Could not parse built in template. Aborting");
+    for (name, default) in [
+        ("index.html", DEFAULT_INDEX),
+        ("view.html", DEFAULT_VIEW),
+        ("sheet.html", DEFAULT_SHEET),
+        ("slideshow.html", DEFAULT_SLIDESHOW),
+    ] {
+        let file_path = Path::new(path).join(name);
+        if file_path.exists() {
+            if let Err(e) = tera.add_template_file(file_path, Some(name)) {
+                warn!("Parsing error(s): {}", e);
+                panic!("Could not parse custom template. Aborting");
+            }
+        } else {
+            info!("No custom {name} found in the current directory so using default template");
+            if let Err(e) = tera.add_raw_template(name, default) {
+                warn!("Parsing error(s): {}", e);
+                panic!("Could not parse built in template. Aborting");
+            }
         }
     }
 
     tera
 }
 
-fn generate_index(
-    templates: &Tera,
-    target_path: &str,
-    images: &Vec<ImageInfo>,
-    background: &str,
-    hot_reload: bool,
-) -> String {
+fn generate_index(data: &AppData, query: &IndexQuery, images: &[ImageInfo]) -> String {
     let mut context = Context::new();
     context.insert("images", images);
-    context.insert("path", target_path);
-    context.insert("background", background);
+    context.insert("path", &data.target_paths.join(", "));
+    context.insert("roots", &data.target_paths);
+    context.insert("selected_root", &query.root);
+    context.insert("folder", &query.folder);
+    context.insert("album", &query.album);
+    context.insert(
+        "albums",
+        &data
+            .annotations
+            .tags
+            .albums()
+            .into_iter()
+            .map(|a| a.name)
+            .collect::<Vec<String>>(),
+    );
+    context.insert("recursive", &data.scan.recursive);
+    // The timeline only makes sense when the images are in date order.
+    let date_order = matches!(data.sort, SortBy::DateNewestFirst | SortBy::DateOldestFirst)
+        || matches!(data.group_by, GroupBy::Day | GroupBy::Month);
+    context.insert("timeline", &date_order);
+    context.insert("background", &data.background);
     context.insert("image_offset", &IMAGE_OFFSET);
+    context.insert("header_height", &HEADER_HEIGHT);
+    context.insert("posters", &data.posters);
+
+    render(data, "index.html", &context)
+}
 
-    if !hot_reload {
-        templates.render("index.html", &context).unwrap()
+fn render(data: &AppData, name: &str, context: &Context) -> String {
+    if !data.hot_reload {
+        data.templates.render(name, context).unwrap()
     } else {
         let templates = create_templates("./src/");
-        templates.render("index.html", &context).unwrap()
+        templates.render(name, context).unwrap()
     }
 }
 
 const DEFAULT_INDEX: &str = include_str!("./index.html");
+const DEFAULT_VIEW: &str = include_str!("./view.html");
+const DEFAULT_SHEET: &str = include_str!("./sheet.html");
+const DEFAULT_SLIDESHOW: &str = include_str!("./slideshow.html");
+/// For a contact sheet asked for without saying how wide the page is.
+const DEFAULT_SHEET_WIDTH: u32 = 1200;
 const IMAGE_OFFSET: u64 = 15;
+/// The height of a group header in the page, including its margins.
+const HEADER_HEIGHT: u64 = 50;
+/// Stops scripts in served svg files from running and from loading anything, while still allowing inline styles.
+const SVG_CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; img-src data:; sandbox";
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::RwLock;
+
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test as actix_test;
+    use actix_web::{web, App};
+    use tempfile::NamedTempFile;
+    use tera::Tera;
+
+    use crate::annotations::Annotations;
+    use crate::duplicates::HashCache;
+    use crate::favourites::Favourites;
+    use crate::file_actions::{FileActions, TrashKind};
+    use crate::group::GroupBy;
+    use crate::image_info::{ImageInfo, ScanOptions, ScanReport};
+    use crate::sniff::MediaType;
+    use crate::tree::tests::{simple_image, test_file};
+    use crate::tree::TreeNode;
+    use crate::{
+        add_favourite, add_to_album, albums_json, apply_culling, contact_sheet_json,
+        create_templates, cull_json, decide, duplicates_json, favourites_json, image_request,
+        images_json, layout, move_image, next_slide, previous_slide, remove_favourite,
+        rename_image, search_json, set_rating, slideshow_json, start_slideshow, stop_slideshow,
+        thumbnail_request, trash_image, undecide, view, AppData, SortBy, HEADER_HEIGHT,
+        IMAGE_OFFSET,
+    };
+
+    pub fn test_app_data(images: Vec<ImageInfo>) -> web::Data<RwLock<AppData>> {
+        web::Data::new(RwLock::new(AppData {
+            target_paths: Vec::new(),
+            tree: TreeNode::tree_from_images(&images),
+            images,
+            sort: SortBy::None,
+            group_by: GroupBy::None,
+            scan: ScanOptions::default(),
+            scan_report: ScanReport::default(),
+            annotations: Annotations::default(),
+            templates: Tera::default(),
+            background: "".to_string(),
+            hot_reload: false,
+            posters: false,
+            write_xmp: false,
+            file_actions: None,
+            hashes: HashCache::default(),
+            slideshow: None,
+        }))
+    }
+
+    fn file_image(file: &NamedTempFile, url: &str, media_type: MediaType) -> ImageInfo {
+        let mut image = simple_image(100);
+        image.url = url.to_string();
+        image.path = file.path().to_path_buf();
+        image.source = file.path().to_string_lossy().to_string();
+        image.media_type = media_type;
+        image.is_video = media_type.is_video();
+        image
+    }
+
+    /// A 1x1 pixel 24 bit bmp
+    fn bmp_file() -> NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(".bmp").tempfile().unwrap();
+        let mut bmp = b"BM".to_vec();
+        bmp.extend(58u32.to_le_bytes());
+        bmp.extend([0, 0, 0, 0]);
+        bmp.extend(54u32.to_le_bytes());
+        bmp.extend(40u32.to_le_bytes());
+        bmp.extend(1i32.to_le_bytes());
+        bmp.extend(1i32.to_le_bytes());
+        bmp.extend(1u16.to_le_bytes());
+        bmp.extend(24u16.to_le_bytes());
+        bmp.extend([0; 24]);
+        bmp.extend([0, 0, 255, 0]);
+        file.write_all(&bmp).unwrap();
+        file
+    }
+
+    #[test]
+    fn group_header_heights() {
+        let mut images: Vec<ImageInfo> = ["a/1.png", "a/2.png", "b/3.png"]
+            .iter()
+            .map(|path| {
+                let mut image = simple_image(100);
+                image.relative_path = path.to_string();
+                image
+            })
+            .collect();
+        layout(&mut images, GroupBy::Folder);
+
+        let step = 100 + IMAGE_OFFSET;
+        let befores: Vec<u64> = images.iter().map(|i| i.height_before).collect();
+        assert_eq!(
+            befores,
+            vec![0, step + HEADER_HEIGHT, 2 * step + HEADER_HEIGHT]
+        );
+        assert_eq!(images[0].height_after, 2 * step + HEADER_HEIGHT);
+        assert_eq!(
+            TreeNode::tree_from_images(&images).unwrap().height(),
+            3 * step + 2 * HEADER_HEIGHT
+        );
+    }
+
+    #[actix_web::test]
+    async fn video_seeking() {
+        let mut video = NamedTempFile::new().unwrap();
+        video.write_all(&[7u8; 1000]).unwrap();
+        let data = test_app_data(vec![file_image(&video, "video.mp4", MediaType::Mp4)]);
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data)
+                .route("/img/{image_name}", web::get().to(image_request)),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/img/video.mp4")
+            .insert_header((header::RANGE, "bytes=500-"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 500-999/1000"
+        );
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "video/mp4"
+        );
+        assert_eq!(actix_test::read_body(res).await.len(), 500);
+    }
+
+    #[actix_web::test]
+    async fn converted_ranges() {
+        let bmp = bmp_file();
+        let data = test_app_data(vec![file_image(&bmp, "picture.bmp", MediaType::Bmp)]);
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data)
+                .route("/img/{image_name}", web::get().to(image_request)),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/img/picture.bmp")
+            .insert_header((header::RANGE, "bytes=0-7"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/png"
+        );
+        let content_range = res.headers().get(header::CONTENT_RANGE).unwrap();
+        assert!(content_range.to_str().unwrap().starts_with("bytes 0-7/"));
+        assert_eq!(actix_test::read_body(res).await, &b"\x89PNG\r\n\x1a\n"[..]);
+    }
+
+    #[actix_web::test]
+    async fn missing_image() {
+        let data = test_app_data(vec![simple_image(100)]);
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data)
+                .route("/img/{image_name}", web::get().to(image_request)),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/img/nothing.png")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn favourites() {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let mut image = simple_image(100);
+        image.root = root_path.clone();
+        image.relative_path = "beach.png".to_string();
+        let data = test_app_data(vec![image]);
+        {
+            let mut data = data.write().unwrap();
+            data.target_paths = vec![root_path.clone()];
+            data.annotations = Annotations::load(&data.target_paths, None);
+        }
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/images", web::get().to(images_json))
+                .route("/api/favourites", web::get().to(favourites_json))
+                .route("/api/favourites/{image_name}", web::put().to(add_favourite))
+                .route(
+                    "/api/favourites/{image_name}",
+                    web::delete().to(remove_favourite),
+                ),
+        )
+        .await;
+        let url = "/api/favourites/0000-0000-33333-33333.png";
+
+        let req = actix_test::TestRequest::put().uri(url).to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        {
+            let data = data.read().unwrap();
+            let Some(TreeNode::Leaf { images }) = &data.tree else {
+                panic!("one image is one leaf");
+            };
+            assert!(images[0].favourite);
+        }
+
+        let req = actix_test::TestRequest::get()
+            .uri("/api/images?filter=is:favourite")
+            .to_request();
+        let images: Vec<serde_json::Value> = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(images.len(), 1);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/api/favourites?format=text")
+            .to_request();
+        let body = actix_test::call_and_read_body(&app, req).await;
+        let expected = format!("{}\n", root.path().join("beach.png").to_string_lossy());
+        assert_eq!(body, expected.as_bytes());
+
+        // Saved for the next start.
+        let loaded = Favourites::load(&data.read().unwrap().annotations.store, &[root_path]);
+        assert_eq!(loaded.list().len(), 1);
+
+        let req = actix_test::TestRequest::delete().uri(url).to_request();
+        actix_test::call_service(&app, req).await;
+        let req = actix_test::TestRequest::get()
+            .uri("/api/images?filter=is:favourite")
+            .to_request();
+        let images: Vec<serde_json::Value> = actix_test::call_and_read_body_json(&app, req).await;
+        assert!(images.is_empty());
+
+        let req = actix_test::TestRequest::put()
+            .uri("/api/favourites/nothing.png")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn albums() {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let images: Vec<ImageInfo> = ["a.png", "b.png", "c.png"]
+            .iter()
+            .map(|name| {
+                let mut image = test_file(&root_path, name);
+                image.url = name.to_string();
+                image
+            })
+            .collect();
+        let data = test_app_data(images);
+        {
+            let mut data = data.write().unwrap();
+            data.target_paths = vec![root_path.clone()];
+            data.annotations = Annotations::load(&data.target_paths, None);
+        }
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/images", web::get().to(images_json))
+                .route("/api/albums", web::get().to(albums_json))
+                .route(
+                    "/api/images/{image_name}/albums/{name}",
+                    web::put().to(add_to_album),
+                ),
+        )
+        .await;
+
+        for url in [
+            "/api/images/c.png/albums/Best%20Of",
+            "/api/images/a.png/albums/Best%20Of",
+        ] {
+            let req = actix_test::TestRequest::put().uri(url).to_request();
+            let res = actix_test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let req = actix_test::TestRequest::put()
+            .uri("/api/images/a.png/albums/%20")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // In page order, laid out as if they were the only images.
+        let req = actix_test::TestRequest::get()
+            .uri("/api/images?album=Best%20Of")
+            .to_request();
+        let album: Vec<serde_json::Value> = actix_test::call_and_read_body_json(&app, req).await;
+        let urls: Vec<&str> = album.iter().map(|i| i["url"].as_str().unwrap()).collect();
+        assert_eq!(urls, vec!["a.png", "c.png"]);
+        assert_eq!(album[1]["height_before"], 100 + IMAGE_OFFSET);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/api/albums")
+            .to_request();
+        let albums: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(albums, serde_json::json!([{"name": "Best Of", "count": 2}]));
+    }
+
+    #[actix_web::test]
+    async fn ratings() {
+        let folder = tempfile::tempdir().unwrap();
+        let mut image = simple_image(100);
+        image.path = folder.path().join("photo.jpg");
+        std::fs::write(&image.path, b"").unwrap();
+        let data = test_app_data(vec![image]);
+        let app = actix_test::init_service(App::new().app_data(data.clone()).route(
+            "/api/images/{image_name}/rating/{rating}",
+            web::put().to(set_rating),
+        ))
+        .await;
+        let url = "/api/images/0000-0000-33333-33333.png/rating/4";
+
+        // Nothing is written without --write-xmp.
+        let req = actix_test::TestRequest::put().uri(url).to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(!folder.path().join("photo.jpg.xmp").exists());
+
+        data.write().unwrap().write_xmp = true;
+        let req = actix_test::TestRequest::put().uri(url).to_request();
+        let image: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(image["rating"], 4);
+        assert!(folder.path().join("photo.jpg.xmp").exists());
+        {
+            let data = data.read().unwrap();
+            let Some(TreeNode::Leaf { images }) = &data.tree else {
+                panic!("one image is one leaf");
+            };
+            assert_eq!(images[0].rating, Some(4));
+        }
+
+        let req = actix_test::TestRequest::put()
+            .uri("/api/images/0000-0000-33333-33333.png/rating/lots")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn file_actions() {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let images: Vec<ImageInfo> = ["beach.png", "snow.png"]
+            .iter()
+            .map(|name| {
+                let mut image = test_file(&root_path, name);
+                image.url = format!("{name}.png");
+                image
+            })
+            .collect();
+        let data = test_app_data(images);
+        {
+            let mut data = data.write().unwrap();
+            data.target_paths = vec![root_path.clone()];
+            data.annotations = Annotations::load(&data.target_paths, None);
+            let data = &mut *data;
+            let annotations = &mut data.annotations;
+            annotations
+                .favourites
+                .set(&annotations.store, &data.images[0], true)
+                .unwrap();
+        }
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/images/{image_name}", web::delete().to(trash_image))
+                .route("/api/images/{image_name}/move", web::put().to(move_image))
+                .route(
+                    "/api/images/{image_name}/rename",
+                    web::put().to(rename_image),
+                ),
+        )
+        .await;
+
+        // Read-only unless started with --allow-write.
+        let req = actix_test::TestRequest::delete()
+            .uri("/api/images/snow.png.png")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(root.path().join("snow.png").exists());
+
+        data.write().unwrap().file_actions = Some(FileActions::new(
+            std::slice::from_ref(&root_path),
+            TrashKind::Folder,
+        ));
+        let req = actix_test::TestRequest::delete()
+            .uri("/api/images/snow.png.png")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(root.path().join(".trash/snow.png").exists());
+        assert_eq!(data.read().unwrap().images.len(), 1);
+
+        let req = actix_test::TestRequest::put()
+            .uri("/api/images/beach.png.png/move?folder=2024")
+            .to_request();
+        let image: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(image["relative_path"], "2024/beach.png");
+        // The id comes from the path, so the page has to use the new one.
+        let url = image["url"].as_str().unwrap().to_string();
+        assert_ne!(url, "beach.png.png");
+        let req = actix_test::TestRequest::put()
+            .uri(&format!("/api/images/{url}/rename?name=sand.png"))
+            .to_request();
+        let image: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(image["relative_path"], "2024/sand.png");
+        let url = image["url"].as_str().unwrap().to_string();
+        assert!(root.path().join("2024/sand.png").exists());
+
+        {
+            let data = data.read().unwrap();
+            assert_eq!(data.images[0].relative_path, "2024/sand.png");
+            assert!(data.annotations.favourites.contains(&data.images[0]));
+            assert!(data.tree.is_some());
+        }
+
+        let req = actix_test::TestRequest::put()
+            .uri(&format!("/api/images/{url}/move?folder=../outside"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn culling() {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        let images: Vec<ImageInfo> = ["beach.png", "snow.png"]
+            .iter()
+            .map(|name| {
+                let mut image = test_file(&root_path, name);
+                image.url = format!("{name}.png");
+                image
+            })
+            .collect();
+        let data = test_app_data(images);
+        {
+            let mut data = data.write().unwrap();
+            data.target_paths = vec![root_path.clone()];
+            data.annotations = Annotations::load(&data.target_paths, None);
+        }
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/cull", web::get().to(cull_json))
+                .route("/api/cull/apply", web::put().to(apply_culling))
+                .route(
+                    "/api/images/{image_name}/cull/{decision}",
+                    web::put().to(decide),
+                )
+                .route("/api/images/{image_name}/cull", web::delete().to(undecide)),
+        )
+        .await;
+
+        for (uri, status) in [
+            (
+                "/api/images/beach.png.png/cull/keep",
+                StatusCode::NO_CONTENT,
+            ),
+            (
+                "/api/images/snow.png.png/cull/reject",
+                StatusCode::NO_CONTENT,
+            ),
+            (
+                "/api/images/snow.png.png/cull/maybe",
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let req = actix_test::TestRequest::put().uri(uri).to_request();
+            let res = actix_test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{uri}");
+        }
+
+        let req = actix_test::TestRequest::get().uri("/api/cull").to_request();
+        let summary: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(summary["keep"], 1);
+        assert_eq!(summary["reject"], 1);
+        let req = actix_test::TestRequest::get()
+            .uri("/api/cull?format=text")
+            .to_request();
+        let body = actix_test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            body,
+            format!("{}\n", root.path().join("snow.png").display())
+        );
+
+        // Moving needs --allow-write, a list doesn't.
+        let req = actix_test::TestRequest::put()
+            .uri("/api/cull/apply?folder=Rejects")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let req = actix_test::TestRequest::put()
+            .uri("/api/cull/apply")
+            .to_request();
+        let result: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(result["lists"].as_array().unwrap().len(), 1);
+
+        // Undone.
+        let req = actix_test::TestRequest::delete()
+            .uri("/api/images/snow.png.png/cull")
+            .to_request();
+        actix_test::call_service(&app, req).await;
+        let req = actix_test::TestRequest::get().uri("/api/cull").to_request();
+        let summary: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(summary["reject"], 0);
+    }
+
+    #[actix_web::test]
+    async fn duplicates() {
+        let root = tempfile::tempdir().unwrap();
+        let images: Vec<ImageInfo> = [("a.svg", "one"), ("b.svg", "two"), ("c.svg", "one")]
+            .iter()
+            .map(|(name, contents)| {
+                let mut image = simple_image(100);
+                image.url = name.to_string();
+                image.path = root.path().join(name);
+                image.source = image.path.to_string_lossy().to_string();
+                image.media_type = MediaType::Svg;
+                std::fs::write(&image.path, contents).unwrap();
+                image
+            })
+            .collect();
+        let data = test_app_data(images);
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/images", web::get().to(images_json))
+                .route("/api/search", web::get().to(search_json))
+                .route("/api/duplicates", web::get().to(duplicates_json)),
+        )
+        .await;
+
+        // Searching among the duplicates hashes the files first, like the page does.
+        let req = actix_test::TestRequest::get()
+            .uri("/api/search?q=fish&duplicates=exact")
+            .to_request();
+        let results: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(results.as_array().unwrap().len(), 2);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/api/duplicates?method=exact")
+            .to_request();
+        let groups: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(groups.as_array().unwrap().len(), 1);
+        assert_eq!(groups[0]["exact"], true);
+        assert_eq!(groups[0]["files"][0]["size"], 3);
+
+        // The page's view of them, with a header for each group.
+        let req = actix_test::TestRequest::get()
+            .uri("/api/images?duplicates=exact")
+            .to_request();
+        let images: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        let urls: Vec<&str> = images
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["url"].as_str().unwrap())
+            .collect();
+        assert_eq!(urls, vec!["a.svg", "c.svg"]);
+        assert_eq!(images[0]["header"], "2 copies");
+        assert_eq!(
+            images[1]["height_before"],
+            100 + IMAGE_OFFSET + HEADER_HEIGHT
+        );
+    }
+
+    #[actix_web::test]
+    async fn view_neighbours() {
+        let images: Vec<ImageInfo> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                let mut image = simple_image(100);
+                image.url = format!("{name}.png");
+                image.relative_path = format!("{name}/{name}.png");
+                image
+            })
+            .collect();
+        let data = test_app_data(images);
+        data.write().unwrap().templates = create_templates("./no-custom-templates/");
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/view/{image_name}", web::get().to(view)),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/view/b.png")
+            .to_request();
+        let body = actix_test::call_and_read_body(&app, req).await;
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains(r#"href="/view/a.png""#));
+        assert!(page.contains(r#"href="/view/c.png""#));
+        assert!(page.contains("2 / 3"));
+
+        // Only the images in the query, and the links keep it.
+        let req = actix_test::TestRequest::get()
+            .uri("/view/c.png?filter=-path:b/*")
+            .to_request();
+        let body = actix_test::call_and_read_body(&app, req).await;
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains(r#"href="/view/a.png?filter=-path:b&#x2F;*""#));
+        assert!(page.contains("2 / 2"));
+
+        let req = actix_test::TestRequest::get()
+            .uri("/view/missing.png")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn contact_sheet() {
+        let bmp = bmp_file();
+        let mut images = vec![file_image(&bmp, "picture.bmp", MediaType::Bmp)];
+        images.extend(["a.png", "b.png"].iter().map(|url| {
+            let mut image = simple_image(100);
+            image.url = url.to_string();
+            image
+        }));
+        let data = test_app_data(images);
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/thumb/{image_name}", web::get().to(thumbnail_request))
+                .route("/api/contact-sheet", web::get().to(contact_sheet_json)),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/thumb/picture.bmp?size=100")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/jpeg"
+        );
+        assert!(actix_test::read_body(res).await.starts_with(&[0xFF, 0xD8]));
+
+        // Two 100px squares across, the third goes on the next row.
+        let req = actix_test::TestRequest::get()
+            .uri("/api/contact-sheet?tile=100&width=220&filter=-type:video")
+            .to_request();
+        let sheet: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(sheet["columns"], 2);
+        assert_eq!(sheet["thumbnail"], 128);
+        let rows: Vec<u64> = sheet["tiles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["y"].as_u64().unwrap())
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], rows[1]);
+        assert!(rows[2] > rows[1]);
+    }
+
+    #[actix_web::test]
+    async fn slideshow() {
+        let images: Vec<ImageInfo> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                let mut image = simple_image(100);
+                image.url = format!("{name}.png");
+                image.relative_path = format!("{name}.png");
+                image
+            })
+            .collect();
+        let data = test_app_data(images);
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/slideshow", web::get().to(slideshow_json))
+                .route("/api/slideshow", web::put().to(start_slideshow))
+                .route("/api/slideshow", web::delete().to(stop_slideshow))
+                .route("/api/slideshow/next", web::put().to(next_slide))
+                .route("/api/slideshow/previous", web::put().to(previous_slide)),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/api/slideshow")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // Only the images in the query, each up for a minute so the test can't move on by itself.
+        let req = actix_test::TestRequest::put()
+            .uri("/api/slideshow?seconds=60&loop=false&transition=slide&filter=-path:b.png")
+            .to_request();
+        let show: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(show["version"], 1);
+        assert_eq!(show["loop"], false);
+        assert_eq!(show["transition"], "slide");
+        assert_eq!(show["total_ms"], 120_000);
+        let urls: Vec<&str> = show["slides"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["url"].as_str().unwrap())
+            .collect();
+        assert_eq!(urls, vec!["a.png", "c.png"]);
+        assert_eq!(show["position"]["index"], 0);
+
+        // Skipping moves the show for every screen.
+        let req = actix_test::TestRequest::put()
+            .uri("/api/slideshow/next")
+            .to_request();
+        actix_test::call_service(&app, req).await;
+        let req = actix_test::TestRequest::get()
+            .uri("/api/slideshow")
+            .to_request();
+        let show: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(show["version"], 2);
+        assert_eq!(show["position"]["index"], 1);
+        let req = actix_test::TestRequest::put()
+            .uri("/api/slideshow/previous")
+            .to_request();
+        let show: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(show["position"]["index"], 0);
+
+        let req = actix_test::TestRequest::put()
+            .uri("/api/slideshow?seconds=1e300")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(data.read().is_ok());
+
+        let req = actix_test::TestRequest::put()
+            .uri("/api/slideshow?filter=path:nothing")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let req = actix_test::TestRequest::delete()
+            .uri("/api/slideshow")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(data.read().unwrap().slideshow.is_none());
+    }
+}
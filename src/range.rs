@@ -0,0 +1,518 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::body::SizedStream;
+use actix_web::http::header::{self, HttpDate};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse};
+use futures_util::stream::{self, LocalBoxStream, StreamExt};
+use uuid::Uuid;
+
+use crate::error::Error;
+
+/// How much of a file is read at a time when streaming it to the browser.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Serves media files with support for byte ranges, which browsers rely on to seek in videos without downloading the
+/// whole file first. Used for original files and anything generated from them (converted images, poster frames) so
+/// they all behave the same way.
+pub struct MediaFile {
+    /// Kept open from when the length and modified time were read, so they match what is sent even if the file is
+    /// replaced in the meantime.
+    file: File,
+    content_type: String,
+    length: u64,
+    modified: SystemTime,
+}
+
+impl MediaFile {
+    pub fn open(path: &Path, content_type: &str) -> Result<MediaFile, Error> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        Ok(MediaFile {
+            file,
+            content_type: content_type.to_string(),
+            length: metadata.len(),
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+        })
+    }
+
+    fn etag(&self) -> String {
+        let modified = self
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        format!("\"{:x}-{:x}\"", self.length, modified)
+    }
+
+    pub fn into_response(self, req: &HttpRequest) -> HttpResponse {
+        let etag = self.etag();
+        let last_modified = HttpDate::from(self.modified).to_string();
+
+        if header_str(req, header::IF_NONE_MATCH)
+            .map(|tags| tags.split(',').any(|t| t.trim() == etag || t.trim() == "*"))
+            .unwrap_or(false)
+        {
+            return HttpResponse::NotModified()
+                .insert_header((header::ETAG, etag))
+                .finish();
+        }
+
+        let ranges = match header_str(req, header::RANGE) {
+            Some(range) if self.if_range_matches(req, &etag) => parse_range(range, self.length),
+            _ => None,
+        };
+
+        let mut builder = HttpResponse::Ok();
+        builder
+            .insert_header((header::ACCEPT_RANGES, "bytes"))
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::LAST_MODIFIED, last_modified));
+
+        match ranges {
+            None => builder
+                .content_type(self.content_type.as_str())
+                .body(SizedStream::new(
+                    self.length,
+                    file_stream(self.file, 0, self.length),
+                )),
+            Some(ranges) if ranges.is_empty() => builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", self.length)))
+                .finish(),
+            Some(ranges) if ranges.len() == 1 => {
+                let range = ranges[0];
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .content_type(self.content_type.as_str())
+                    .insert_header((header::CONTENT_RANGE, range.content_range(self.length)))
+                    .body(SizedStream::new(
+                        range.length(),
+                        file_stream(self.file, range.start, range.length()),
+                    ))
+            }
+            Some(ranges) => {
+                let boundary = Uuid::new_v4().simple().to_string();
+                let (length, body) = self.multipart_body(&ranges, &boundary);
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .content_type(format!("multipart/byteranges; boundary={boundary}"))
+                    .body(SizedStream::new(length, body))
+            }
+        }
+    }
+
+    /// A range request is only honoured if the browser's copy is the same version of the file, otherwise the whole
+    /// file is sent so it doesn't stitch together pieces of two different files.
+    fn if_range_matches(&self, req: &HttpRequest, etag: &str) -> bool {
+        match header_str(req, header::IF_RANGE) {
+            None => true,
+            Some(value) if value.starts_with('"') => value == etag,
+            Some(value) => match HttpDate::from_str(value) {
+                Ok(date) => whole_seconds(SystemTime::from(date)) == whole_seconds(self.modified),
+                Err(_) => false,
+            },
+        }
+    }
+
+    fn multipart_body(
+        &self,
+        ranges: &[ByteRange],
+        boundary: &str,
+    ) -> (u64, LocalBoxStream<'static, Result<Bytes, Error>>) {
+        let mut length = 0;
+        let mut parts = Vec::new();
+        for range in ranges {
+            let part_header = format!(
+                "\r\n--{boundary}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                self.content_type,
+                range.content_range(self.length)
+            );
+            length += part_header.len() as u64 + range.length();
+            let part = match self.file.try_clone() {
+                Ok(file) => file_stream(file, range.start, range.length()),
+                Err(e) => stream::once(async move { Err(e.into()) }).boxed_local(),
+            };
+            parts.push(
+                stream::once(async move { Ok(Bytes::from(part_header)) })
+                    .chain(part)
+                    .boxed_local(),
+            );
+        }
+        let closing = format!("\r\n--{boundary}--\r\n");
+        length += closing.len() as u64;
+        parts.push(stream::once(async move { Ok(Bytes::from(closing)) }).boxed_local());
+
+        (length, stream::iter(parts).flatten().boxed_local())
+    }
+}
+
+/// An inclusive range of bytes in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+impl ByteRange {
+    fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// Parses a `Range` header. Returns `None` if the header isn't a byte range we understand, in which case it is
+/// ignored and the whole file is sent. An empty list means none of the ranges overlap the file.
+fn parse_range(header: &str, length: u64) -> Option<Vec<ByteRange>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+
+    let mut ranges: Vec<ByteRange> = Vec::new();
+    for spec in specs.split(',') {
+        let (start, end) = spec.trim().split_once('-')?;
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return None,
+            // the last n bytes
+            ("", suffix) => {
+                let suffix: u64 = suffix.parse().ok()?;
+                if suffix == 0 || length == 0 {
+                    continue;
+                }
+                ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length - 1,
+                }
+            }
+            (start, end) => {
+                let start: u64 = start.parse().ok()?;
+                let end: u64 = if end.is_empty() {
+                    u64::MAX
+                } else {
+                    end.parse().ok()?
+                };
+                if end < start {
+                    return None;
+                }
+                if start >= length {
+                    continue;
+                }
+                ByteRange {
+                    start,
+                    end: end.min(length - 1),
+                }
+            }
+        };
+
+        ranges.push(range);
+    }
+
+    // Overlapping or touching ranges are merged so a client can't ask for the same bytes over and over.
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    Some(merged)
+}
+
+/// Reads `length` bytes from `start`. The seek waits for the first chunk, as the ranges of a multipart response share
+/// one handle and are read one after another.
+fn file_stream(
+    file: File,
+    start: u64,
+    length: u64,
+) -> LocalBoxStream<'static, Result<Bytes, Error>> {
+    stream::unfold(
+        (Some(file), Some(start), length),
+        move |(file, seek, remaining): (Option<File>, Option<u64>, u64)| async move {
+            let mut file = file?;
+            if remaining == 0 {
+                return None;
+            }
+            let chunk_length = remaining.min(CHUNK_SIZE);
+            // File reads block, so they are done on the blocking thread pool rather than holding up the server.
+            let chunk = web::block(move || -> Result<(File, Bytes), Error> {
+                if let Some(start) = seek {
+                    file.seek(SeekFrom::Start(start))?;
+                }
+                let mut buffer = vec![0; chunk_length as usize];
+                file.read_exact(&mut buffer)?;
+                Ok((file, Bytes::from(buffer)))
+            })
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
+            .and_then(|r| r);
+            match chunk {
+                Ok((file, chunk)) => {
+                    Some((Ok(chunk), (Some(file), None, remaining - chunk_length)))
+                }
+                // stop after reporting the error, the response can't be finished
+                Err(e) => Some((Err(e), (None, None, 0))),
+            }
+        },
+    )
+    .boxed_local()
+}
+
+fn header_str(req: &HttpRequest, name: header::HeaderName) -> Option<&str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn whole_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use actix_web::body::{to_bytes, BodySize, MessageBody};
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test as actix_test;
+    use actix_web::{web, App, HttpRequest, HttpResponse};
+    use tempfile::NamedTempFile;
+
+    use crate::range::{parse_range, ByteRange, MediaFile};
+
+    const CONTENTS: &[u8] = b"0123456789abcdefghij";
+
+    fn media_file() -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(CONTENTS).unwrap();
+        file
+    }
+
+    async fn serve(req: HttpRequest, path: web::Data<PathBuf>) -> HttpResponse {
+        MediaFile::open(&path, "video/mp4")
+            .unwrap()
+            .into_response(&req)
+    }
+
+    macro_rules! service {
+        ($file:expr) => {
+            actix_test::init_service(
+                App::new()
+                    .app_data(web::Data::new($file.path().to_path_buf()))
+                    .route("/", web::get().to(serve)),
+            )
+            .await
+        };
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-4", 20),
+            Some(vec![ByteRange { start: 0, end: 4 }])
+        );
+        assert_eq!(
+            parse_range("bytes=15-", 20),
+            Some(vec![ByteRange { start: 15, end: 19 }])
+        );
+        assert_eq!(
+            parse_range("bytes=-5", 20),
+            Some(vec![ByteRange { start: 15, end: 19 }])
+        );
+        assert_eq!(
+            parse_range("bytes=0-100", 20),
+            Some(vec![ByteRange { start: 0, end: 19 }])
+        );
+        // overlapping ranges are merged
+        assert_eq!(
+            parse_range("bytes=0-4, 3-6", 20),
+            Some(vec![ByteRange { start: 0, end: 6 }])
+        );
+        // however they are ordered
+        assert_eq!(
+            parse_range("bytes=0-10,20-30,0-30", 40),
+            Some(vec![ByteRange { start: 0, end: 30 }])
+        );
+        assert_eq!(
+            parse_range("bytes=0-1,5-6,2-4", 20),
+            Some(vec![ByteRange { start: 0, end: 6 }])
+        );
+        assert_eq!(parse_range("bytes=30-40", 20), Some(vec![]));
+        assert_eq!(parse_range("bytes=5-1", 20), None);
+        assert_eq!(parse_range("lines=1-2", 20), None);
+    }
+
+    #[actix_web::test]
+    async fn no_range() {
+        let file = media_file();
+        let app = service!(file);
+
+        let res =
+            actix_test::call_service(&app, actix_test::TestRequest::get().uri("/").to_request())
+                .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(actix_test::read_body(res).await, CONTENTS);
+    }
+
+    #[actix_web::test]
+    async fn single_range() {
+        let file = media_file();
+        let app = service!(file);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::RANGE, "bytes=2-5"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 2-5/20"
+        );
+        // the length is sent as the content-length header
+        assert_eq!(res.response().body().size(), BodySize::Sized(4));
+        assert_eq!(actix_test::read_body(res).await, &b"2345"[..]);
+    }
+
+    #[actix_web::test]
+    async fn suffix_range() {
+        let file = media_file();
+        let app = service!(file);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::RANGE, "bytes=-3"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 17-19/20"
+        );
+        assert_eq!(actix_test::read_body(res).await, &b"hij"[..]);
+    }
+
+    #[actix_web::test]
+    async fn multiple_ranges() {
+        let file = media_file();
+        let app = service!(file);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::RANGE, "bytes=0-1,10-12"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(content_type.starts_with("multipart/byteranges; boundary="));
+        let boundary = content_type.split_once("boundary=").unwrap().1.to_string();
+        let content_length = res.response().body().size();
+
+        let body = actix_test::read_body(res).await;
+        assert_eq!(content_length, BodySize::Sized(body.len() as u64));
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let expected = format!(
+            "\r\n--{boundary}\r\nContent-Type: video/mp4\r\nContent-Range: bytes 0-1/20\r\n\r\n01\
+             \r\n--{boundary}\r\nContent-Type: video/mp4\r\nContent-Range: bytes 10-12/20\r\n\r\nabc\
+             \r\n--{boundary}--\r\n"
+        );
+        assert_eq!(body, expected);
+    }
+
+    #[actix_web::test]
+    async fn several_chunks() {
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&contents).unwrap();
+        let app = service!(file);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::RANGE, "bytes=1000-150000"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(actix_test::read_body(res).await, &contents[1000..=150_000]);
+    }
+
+    #[actix_web::test]
+    async fn replaced_after_open() {
+        let file = media_file();
+        let media = MediaFile::open(file.path(), "video/mp4").unwrap();
+        // The file that was opened is the one sent, matching the length already worked out from it.
+        fs::remove_file(file.path()).unwrap();
+        fs::write(file.path(), "something else").unwrap();
+
+        let res = media.into_response(&actix_test::TestRequest::get().to_http_request());
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(to_bytes(res.into_body()).await.unwrap(), CONTENTS);
+    }
+
+    #[actix_web::test]
+    async fn unsatisfiable_range() {
+        let file = media_file();
+        let app = service!(file);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::RANGE, "bytes=50-60"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            res.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes */20"
+        );
+    }
+
+    #[actix_web::test]
+    async fn if_range() {
+        let file = media_file();
+        let app = service!(file);
+
+        let res =
+            actix_test::call_service(&app, actix_test::TestRequest::get().uri("/").to_request())
+                .await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        let last_modified = res.headers().get(header::LAST_MODIFIED).unwrap().clone();
+
+        for validator in [etag, last_modified] {
+            let req = actix_test::TestRequest::get()
+                .uri("/")
+                .insert_header((header::RANGE, "bytes=0-3"))
+                .insert_header((header::IF_RANGE, validator))
+                .to_request();
+            let res = actix_test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(actix_test::read_body(res).await, &b"0123"[..]);
+        }
+
+        // a different version of the file gets the whole thing
+        let req = actix_test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::RANGE, "bytes=0-3"))
+            .insert_header((header::IF_RANGE, "\"something-else\""))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(actix_test::read_body(res).await, CONTENTS);
+    }
+}
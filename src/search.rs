@@ -0,0 +1,182 @@
+use globset::GlobBuilder;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::image_info::ImageInfo;
+
+/// How the search text is matched against the paths.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// A glob if the text has any of `*?[{` in it, otherwise substring matches followed by fuzzy ones.
+    #[default]
+    Auto,
+    Substring,
+    Glob,
+    Fuzzy,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub url: String,
+    pub root: String,
+    pub relative_path: String,
+    /// How far down the page the image is, from the layout.
+    pub offset: u64,
+}
+
+/// Finds the images whose path from their root matches the text, best matches first. Ignores case.
+pub fn search(
+    images: &[ImageInfo],
+    text: &str,
+    mode: SearchMode,
+    limit: usize,
+) -> Result<Vec<SearchResult>, Error> {
+    let mode = match mode {
+        SearchMode::Auto if text.contains(['*', '?', '[', '{']) => SearchMode::Glob,
+        mode => mode,
+    };
+    let needle = text.to_lowercase();
+
+    let mut scored: Vec<(u32, &ImageInfo)> = match mode {
+        SearchMode::Glob => {
+            let glob = GlobBuilder::new(text)
+                .case_insensitive(true)
+                .build()?
+                .compile_matcher();
+            images
+                .iter()
+                .filter(|i| glob.is_match(&i.relative_path))
+                .map(|i| (0, i))
+                .collect()
+        }
+        _ => images
+            .iter()
+            .filter_map(|i| {
+                let haystack = i.relative_path.to_lowercase();
+                let score = match mode {
+                    SearchMode::Substring => substring_score(&needle, &haystack),
+                    SearchMode::Fuzzy => fuzzy_score(&needle, &haystack),
+                    _ => substring_score(&needle, &haystack)
+                        .or_else(|| fuzzy_score(&needle, &haystack)),
+                };
+                score.map(|score| (score, i))
+            })
+            .collect(),
+    };
+
+    // Stable, so equally good matches stay in the order they are in the page.
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+    Ok(scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, i)| SearchResult {
+            url: i.url.clone(),
+            root: i.root.clone(),
+            relative_path: i.relative_path.clone(),
+            offset: i.height_before,
+        })
+        .collect())
+}
+
+/// Substring matches always beat fuzzy ones. A match in the file name beats one in the folders, then earlier is
+/// better.
+fn substring_score(needle: &str, haystack: &str) -> Option<u32> {
+    let position = haystack.find(needle)?;
+    let name_start = haystack.rfind('/').map(|i| i + 1).unwrap_or(0);
+    let in_name = if position >= name_start { 1000 } else { 0 };
+    Some(2000 + in_name - (position.min(999) as u32))
+}
+
+/// The needle's characters have to appear in order, the fewer characters skipped between them the better.
+fn fuzzy_score(needle: &str, haystack: &str) -> Option<u32> {
+    let mut needle_chars = needle.chars().peekable();
+    let mut started = false;
+    let mut skipped = 0;
+    for c in haystack.chars() {
+        match needle_chars.peek() {
+            Some(wanted) if *wanted == c => {
+                started = true;
+                needle_chars.next();
+            }
+            Some(_) if started => skipped += 1,
+            Some(_) => {}
+            None => break,
+        }
+    }
+
+    if needle_chars.peek().is_some() {
+        return None;
+    }
+    Some(1000 - skipped.min(999))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image_info::ImageInfo;
+    use crate::search::{search, SearchMode};
+    use crate::tree::tests::simple_image;
+
+    fn images(paths: &[&str]) -> Vec<ImageInfo> {
+        paths
+            .iter()
+            .enumerate()
+            .map(|(n, path)| {
+                let mut image = simple_image(100);
+                image.relative_path = path.to_string();
+                image.url = path.to_string();
+                image.height_before = n as u64 * 100;
+                image
+            })
+            .collect()
+    }
+
+    fn found(images: &[ImageInfo], text: &str, mode: SearchMode) -> Vec<String> {
+        search(images, text, mode, 50)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.relative_path)
+            .collect()
+    }
+
+    #[test]
+    fn substring_before_fuzzy() {
+        let images = images(&[
+            "beach/sand.png",
+            "2024/Beach.jpg",
+            "b/e/a/c/h.png",
+            "snow.png",
+        ]);
+
+        assert_eq!(
+            found(&images, "beach", SearchMode::Auto),
+            vec!["2024/Beach.jpg", "beach/sand.png", "b/e/a/c/h.png"]
+        );
+        assert_eq!(
+            found(&images, "beach", SearchMode::Substring),
+            vec!["2024/Beach.jpg", "beach/sand.png"]
+        );
+        assert_eq!(found(&images, "snw", SearchMode::Fuzzy), vec!["snow.png"]);
+    }
+
+    #[test]
+    fn glob() {
+        let images = images(&["beach/sand.png", "2024/beach.jpg", "snow.PNG"]);
+
+        assert_eq!(
+            found(&images, "*.png", SearchMode::Auto),
+            vec!["beach/sand.png", "snow.PNG"]
+        );
+        assert!(search(&images, "[", SearchMode::Glob, 50).is_err());
+    }
+
+    #[test]
+    fn offsets_and_limit() {
+        let images = images(&["a1.png", "a2.png", "a3.png"]);
+
+        let results = search(&images, "a", SearchMode::Auto, 2).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].offset, 100);
+    }
+}
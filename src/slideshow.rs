@@ -0,0 +1,316 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::image_info::ImageInfo;
+
+pub const DEFAULT_IMAGE_SECONDS: f64 = 5.0;
+/// Shorter than this and the transitions would take up most of the time.
+pub const MIN_IMAGE_MS: u64 = 1000;
+/// A day, anything longer is a mistake rather than a slideshow.
+pub const MAX_IMAGE_SECONDS: f64 = 86_400.0;
+
+/// How one slide gives way to the next. Done by the page, the server only passes it on.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    None,
+    #[default]
+    Fade,
+    Slide,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SlideshowOptions {
+    /// How long each still image stays up.
+    pub image_ms: u64,
+    pub shuffle: bool,
+    pub looping: bool,
+    pub transition: Transition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Slide {
+    pub url: String,
+    pub is_video: bool,
+    /// From the start of the show.
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+/// The one show the server is running. Every screen showing it works out which slide should be up from the start
+/// time and its own clock, corrected by the server's, so they all stay on the same slide without talking to each
+/// other.
+#[derive(Debug, Clone, Serialize)]
+pub struct Slideshow {
+    /// Goes up whenever the show is started again or skipped, so screens know to catch up.
+    pub version: u64,
+    /// When the first slide went up, in milliseconds since the unix epoch. Skipping moves it.
+    pub started_ms: u64,
+    pub image_ms: u64,
+    pub shuffle: bool,
+    #[serde(rename = "loop")]
+    pub looping: bool,
+    pub transition: Transition,
+    pub total_ms: u64,
+    pub slides: Vec<Slide>,
+}
+
+/// Which slide should be showing, and how far into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Position {
+    pub index: usize,
+    pub offset_ms: u64,
+}
+
+impl Slideshow {
+    /// A show of the images in the order given, or shuffled, starting now. None if there is nothing to show.
+    pub fn new(
+        images: &[ImageInfo],
+        options: &SlideshowOptions,
+        now_ms: u64,
+        version: u64,
+    ) -> Option<Slideshow> {
+        let image_ms = options.image_ms.max(MIN_IMAGE_MS);
+        let mut slides: Vec<Slide> = images
+            .iter()
+            .map(|image| Slide {
+                url: image.url.clone(),
+                is_video: image.is_video,
+                start_ms: 0,
+                duration_ms: slide_duration(image, image_ms),
+            })
+            .collect();
+        if slides.is_empty() {
+            return None;
+        }
+        if options.shuffle {
+            slides.shuffle(&mut rand::rng());
+        }
+
+        // Saturating, as a video can claim to be any length.
+        let mut total_ms: u64 = 0;
+        for slide in slides.iter_mut() {
+            slide.start_ms = total_ms;
+            total_ms = total_ms.saturating_add(slide.duration_ms);
+        }
+
+        Some(Slideshow {
+            version,
+            started_ms: now_ms,
+            image_ms,
+            shuffle: options.shuffle,
+            looping: options.looping,
+            transition: options.transition,
+            total_ms,
+            slides,
+        })
+    }
+
+    /// None once a show that doesn't loop has finished.
+    pub fn position(&self, now_ms: u64) -> Option<Position> {
+        let mut elapsed = now_ms.saturating_sub(self.started_ms);
+        if self.looping {
+            elapsed %= self.total_ms;
+        } else if elapsed >= self.total_ms {
+            return None;
+        }
+
+        // The last slide starting at or before now.
+        let index = self
+            .slides
+            .partition_point(|slide| slide.start_ms <= elapsed)
+            - 1;
+        Some(Position {
+            index,
+            offset_ms: elapsed - self.slides[index].start_ms,
+        })
+    }
+
+    /// Moves every screen on to the start of the next slide, or back to the start of the one before. Going past the
+    /// end of a show that doesn't loop finishes it.
+    pub fn skip(&mut self, now_ms: u64, forward: bool) {
+        let last = self.slides.len() - 1;
+        let target = match (self.position(now_ms), forward) {
+            // Finished, so back goes to the last slide and forward stays finished.
+            (None, true) => None,
+            (None, false) => Some(last),
+            (Some(position), true) if position.index == last => self.looping.then_some(0),
+            (Some(position), true) => Some(position.index + 1),
+            (Some(position), false) if position.index == 0 => {
+                Some(if self.looping { last } else { 0 })
+            }
+            (Some(position), false) => Some(position.index - 1),
+        };
+
+        // Only where the show started changes, the slides stay as they are.
+        self.started_ms = match target {
+            Some(index) => now_ms.saturating_sub(self.slides[index].start_ms),
+            None => now_ms.saturating_sub(self.total_ms),
+        };
+        self.version += 1;
+    }
+}
+
+/// Videos play to the end. Animations play through as many times as fit in the time for an image, at least once.
+/// Anything whose length isn't known gets the time for an image.
+fn slide_duration(image: &ImageInfo, image_ms: u64) -> u64 {
+    match image.duration_ms {
+        0 => image_ms,
+        duration if image.is_video => duration,
+        duration if image.animated => image_ms.div_ceil(duration).saturating_mul(duration),
+        _ => image_ms,
+    }
+}
+
+/// How long a still image stays up, from the seconds asked for.
+pub fn image_ms(seconds: f64) -> Result<u64, Error> {
+    // NaN isn't in any range.
+    if !(0.0..=MAX_IMAGE_SECONDS).contains(&seconds) {
+        return Err(Error::InvalidSeconds(seconds));
+    }
+    Ok((seconds * 1000.0) as u64)
+}
+
+/// The server's clock, sent to the screens so they can correct for their own.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::image_info::ImageInfo;
+    use crate::slideshow::{image_ms, Position, Slideshow, SlideshowOptions, Transition};
+    use crate::tree::tests::simple_image;
+
+    fn image(url: &str, is_video: bool, animated: bool, duration_ms: u64) -> ImageInfo {
+        let mut image = simple_image(100);
+        image.url = url.to_string();
+        image.is_video = is_video;
+        image.animated = animated;
+        image.duration_ms = duration_ms;
+        image
+    }
+
+    fn options(looping: bool) -> SlideshowOptions {
+        SlideshowOptions {
+            image_ms: 5000,
+            shuffle: false,
+            looping,
+            transition: Transition::Fade,
+        }
+    }
+
+    #[test]
+    fn durations() {
+        let images = vec![
+            image("still", false, false, 0),
+            image("video", true, false, 12_345),
+            // 1.5s plays through four times to fill the 5s.
+            image("animation", false, true, 1500),
+            // No duration could be found.
+            image("unknown", true, false, 0),
+        ];
+        let show = Slideshow::new(&images, &options(false), 1_000_000, 1).unwrap();
+        let timings: Vec<(&str, u64, u64)> = show
+            .slides
+            .iter()
+            .map(|s| (s.url.as_str(), s.start_ms, s.duration_ms))
+            .collect();
+        assert_eq!(
+            timings,
+            vec![
+                ("still", 0, 5000),
+                ("video", 5000, 12_345),
+                ("animation", 17_345, 6000),
+                ("unknown", 23_345, 5000),
+            ]
+        );
+        assert_eq!(show.total_ms, 28_345);
+
+        assert!(Slideshow::new(&[], &options(false), 0, 1).is_none());
+    }
+
+    #[test]
+    fn long_shows() {
+        assert_eq!(image_ms(2.5).unwrap(), 2500);
+        for seconds in [1e300, f64::NAN, -1.0] {
+            assert!(
+                matches!(image_ms(seconds), Err(Error::InvalidSeconds(_))),
+                "{seconds}"
+            );
+        }
+
+        // Videos that claim to go on forever don't overflow the total.
+        let images = vec![
+            image("a", true, false, u64::MAX),
+            image("b", true, false, u64::MAX),
+            image("c", false, true, 3),
+        ];
+        let mut options = options(true);
+        options.image_ms = u64::MAX;
+        let mut show = Slideshow::new(&images, &options, 1_000_000, 1).unwrap();
+        assert_eq!(show.total_ms, u64::MAX);
+        assert_eq!(show.position(1_000_000).unwrap().index, 0);
+        show.skip(1_000_000, true);
+        assert_eq!(show.position(1_000_000).unwrap().index, 0);
+    }
+
+    #[test]
+    fn positions_and_skipping() {
+        let images = vec![
+            image("a", false, false, 0),
+            image("b", true, false, 10_000),
+            image("c", false, false, 0),
+        ];
+        let start = 1_000_000;
+        let mut show = Slideshow::new(&images, &options(false), start, 1).unwrap();
+        assert_eq!(
+            show.position(start + 7000),
+            Some(Position {
+                index: 1,
+                offset_ms: 2000
+            })
+        );
+        assert_eq!(show.position(start + 20_000), None);
+
+        // Looping wraps round.
+        let mut looping = Slideshow::new(&images, &options(true), start, 1).unwrap();
+        assert_eq!(
+            looping.position(start + 20_000 + 1000),
+            Some(Position {
+                index: 0,
+                offset_ms: 1000
+            })
+        );
+
+        // Next goes to the start of the following slide, for everyone, from now.
+        let now = start + 7000;
+        show.skip(now, true);
+        assert_eq!(show.version, 2);
+        assert_eq!(
+            show.position(now),
+            Some(Position {
+                index: 2,
+                offset_ms: 0
+            })
+        );
+        // Past the end of a show that doesn't loop finishes it, and back again is the last slide.
+        show.skip(now, true);
+        assert_eq!(show.position(now), None);
+        show.skip(now, false);
+        assert_eq!(show.position(now).unwrap().index, 2);
+
+        // Back from the first slide of a looping show is the last.
+        looping.skip(start, false);
+        assert_eq!(looping.position(start).unwrap().index, 2);
+        looping.skip(start, true);
+        assert_eq!(looping.position(start).unwrap().index, 0);
+    }
+}
@@ -0,0 +1,240 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// The kinds of file we know how to display, decided from the contents of the file rather than trusting its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Svg,
+    Bmp,
+    Tiff,
+    Ico,
+    Mp4,
+    Webm,
+}
+
+impl MediaType {
+    pub fn from_extension(extension: &str) -> Option<MediaType> {
+        match extension.to_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(MediaType::Jpeg),
+            "png" => Some(MediaType::Png),
+            "gif" => Some(MediaType::Gif),
+            "webp" => Some(MediaType::Webp),
+            "svg" => Some(MediaType::Svg),
+            "bmp" => Some(MediaType::Bmp),
+            "tif" | "tiff" => Some(MediaType::Tiff),
+            "ico" => Some(MediaType::Ico),
+            "mp4" => Some(MediaType::Mp4),
+            "webm" => Some(MediaType::Webm),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MediaType::Jpeg => "jpg",
+            MediaType::Png => "png",
+            MediaType::Gif => "gif",
+            MediaType::Webp => "webp",
+            MediaType::Svg => "svg",
+            MediaType::Bmp => "bmp",
+            MediaType::Tiff => "tiff",
+            MediaType::Ico => "ico",
+            MediaType::Mp4 => "mp4",
+            MediaType::Webm => "webm",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            MediaType::Jpeg => "image/jpeg",
+            MediaType::Png => "image/png",
+            MediaType::Gif => "image/gif",
+            MediaType::Webp => "image/webp",
+            MediaType::Svg => "image/svg+xml",
+            MediaType::Bmp => "image/bmp",
+            MediaType::Tiff => "image/tiff",
+            MediaType::Ico => "image/x-icon",
+            MediaType::Mp4 => "video/mp4",
+            MediaType::Webm => "video/webm",
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(self, MediaType::Mp4 | MediaType::Webm)
+    }
+
+    /// Browsers can't display these reliably so they get converted to png before being served.
+    pub fn needs_conversion(&self) -> bool {
+        matches!(self, MediaType::Bmp | MediaType::Tiff)
+    }
+
+    /// The formats that can hold more than one frame.
+    pub fn can_animate(&self) -> bool {
+        matches!(self, MediaType::Gif | MediaType::Png | MediaType::Webp)
+    }
+}
+
+/// How much of the start of a file to read. Svg files can start with a long xml preamble or comment before the root
+/// element so this is more than the binary formats need.
+const SNIFF_LENGTH: u64 = 1024;
+
+pub fn sniff(path: &Path) -> Result<Option<MediaType>, Error> {
+    let mut header = Vec::new();
+    File::open(path)?
+        .take(SNIFF_LENGTH)
+        .read_to_end(&mut header)?;
+    Ok(sniff_bytes(&header))
+}
+
+pub fn sniff_bytes(header: &[u8]) -> Option<MediaType> {
+    if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(MediaType::Jpeg)
+    } else if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(MediaType::Png)
+    } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        Some(MediaType::Gif)
+    } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+        Some(MediaType::Webp)
+    } else if looks_like_bmp(header) {
+        Some(MediaType::Bmp)
+    } else if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+        Some(MediaType::Tiff)
+    } else if header.starts_with(&[0, 0, 1, 0]) && le_u16(header, 4).is_some_and(|count| count > 0)
+    {
+        // With at least one image in it.
+        Some(MediaType::Ico)
+    } else if header.get(4..8) == Some(b"ftyp") {
+        // heif and avif images use the same container as mp4, the brand tells them apart.
+        match header.get(8..12) {
+            Some(b"heic" | b"heix" | b"mif1" | b"msf1" | b"avif") => None,
+            _ => Some(MediaType::Mp4),
+        }
+    } else if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        // webm is a restricted kind of matroska, only claim the files that say they are webm.
+        contains(header, b"webm").then_some(MediaType::Webm)
+    } else if looks_like_svg(header) {
+        Some(MediaType::Svg)
+    } else {
+        None
+    }
+}
+
+/// `BM` alone starts plenty of other files. The file's size has to at least cover its headers, and the second header
+/// has to be the size of one of the versions of it.
+fn looks_like_bmp(header: &[u8]) -> bool {
+    let (Some(file_size), Some(info_size)) = (le_u32(header, 2), le_u32(header, 14)) else {
+        return false;
+    };
+    header.starts_with(b"BM")
+        && matches!(info_size, 12 | 40 | 52 | 56 | 64 | 108 | 124)
+        && file_size >= 14 + info_size
+}
+
+fn le_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    let bytes = bytes.get(at..at + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let bytes = bytes.get(at..at + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn looks_like_svg(header: &[u8]) -> bool {
+    let text = header.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(header);
+    let start = text.iter().position(|b| !b.is_ascii_whitespace());
+    match start {
+        Some(start) => text[start] == b'<' && contains(text, b"<svg"),
+        None => false,
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sniff::{sniff_bytes, MediaType};
+
+    #[test]
+    fn sniff_images() {
+        assert_eq!(
+            sniff_bytes(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10]),
+            Some(MediaType::Jpeg)
+        );
+        assert_eq!(
+            sniff_bytes(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"),
+            Some(MediaType::Png)
+        );
+        assert_eq!(sniff_bytes(b"GIF89a\x01\0"), Some(MediaType::Gif));
+        assert_eq!(
+            sniff_bytes(b"RIFF\x24\0\0\0WEBPVP8 "),
+            Some(MediaType::Webp)
+        );
+        assert_eq!(sniff_bytes(b"II*\0\x08\0\0\0"), Some(MediaType::Tiff));
+        assert_eq!(
+            sniff_bytes(b"BM\x3a\0\0\0\0\0\0\0\x36\0\0\0\x28\0\0\0"),
+            Some(MediaType::Bmp)
+        );
+        assert_eq!(
+            sniff_bytes(b"\0\0\x01\0\x01\0\x10\x10"),
+            Some(MediaType::Ico)
+        );
+    }
+
+    #[test]
+    fn sniff_lookalikes() {
+        // Text that happens to start with BM.
+        assert_eq!(sniff_bytes(b"BMW 3 series service history"), None);
+        // A header size no version of bmp has, or a file too small to hold it.
+        assert_eq!(
+            sniff_bytes(b"BM\x3a\0\0\0\0\0\0\0\x36\0\0\0\x29\0\0\0"),
+            None
+        );
+        assert_eq!(
+            sniff_bytes(b"BM\x10\0\0\0\0\0\0\0\x36\0\0\0\x28\0\0\0"),
+            None
+        );
+        assert_eq!(sniff_bytes(b"BM\x3a\0"), None);
+        // An icon with no images.
+        assert_eq!(sniff_bytes(b"\0\0\x01\0\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn sniff_video() {
+        assert_eq!(
+            sniff_bytes(b"\0\0\0\x20ftypisom\0\0\x02\0"),
+            Some(MediaType::Mp4)
+        );
+        assert_eq!(sniff_bytes(b"\0\0\0\x1cftypheic\0\0\0\0"), None);
+        assert_eq!(
+            sniff_bytes(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84webm"),
+            Some(MediaType::Webm)
+        );
+        assert_eq!(
+            sniff_bytes(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x88matroska"),
+            None
+        );
+    }
+
+    #[test]
+    fn sniff_svg() {
+        assert_eq!(
+            sniff_bytes(b"\xEF\xBB\xBF  <?xml version=\"1.0\"?>\n<svg viewBox=\"0 0 1 1\"/>"),
+            Some(MediaType::Svg)
+        );
+        assert_eq!(sniff_bytes(b"<html><body></body></html>"), None);
+        assert_eq!(sniff_bytes(b"just some text <svg"), None);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::error::Error;
+
+/// The folder made in each root to keep favourites and the like, hidden so it's never scanned.
+pub const STORE_FOLDER: &str = ".picture_browser";
+
+/// Where the things picture browser remembers about each root are kept, as json files. Everything in them is keyed by
+/// the path from the root so they still apply after a restart or if the root is moved.
+#[derive(Debug, Clone, Default)]
+pub struct Store {
+    folders: HashMap<String, PathBuf>,
+}
+
+impl Store {
+    /// Without a data folder each root gets its own store folder inside it, or next to it for a single file.
+    pub fn new(roots: &[String], data_dir: Option<&Path>) -> Store {
+        let folders = roots
+            .iter()
+            .map(|root| {
+                let root_path = Path::new(root);
+                let folder = match data_dir {
+                    Some(data_dir) => data_dir.join(folder_name(root_path)),
+                    None if root_path.is_file() => root_path
+                        .parent()
+                        .unwrap_or(Path::new("."))
+                        .join(STORE_FOLDER)
+                        .join(root_path.file_name().unwrap_or_default()),
+                    None => root_path.join(STORE_FOLDER),
+                };
+                (root.clone(), folder)
+            })
+            .collect();
+        Store { folders }
+    }
+
+    pub fn folder(&self, root: &str) -> Result<&Path, Error> {
+        self.folders
+            .get(root)
+            .map(PathBuf::as_path)
+            .ok_or_else(|| Error::NotFound(root.to_string()))
+    }
+
+    /// Reads one of the files, an empty value if it hasn't been saved yet.
+    pub fn load<T: DeserializeOwned + Default>(&self, root: &str, name: &str) -> Result<T, Error> {
+        let path = self.folder(root)?.join(name);
+        if !path.exists() {
+            return Ok(T::default());
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save<T: Serialize>(&self, root: &str, name: &str, value: &T) -> Result<(), Error> {
+        self.write(root, name, &serde_json::to_vec_pretty(value)?)?;
+        Ok(())
+    }
+
+    /// Returns where it was written.
+    pub fn write(&self, root: &str, name: &str, contents: &[u8]) -> Result<PathBuf, Error> {
+        let folder = self.folder(root)?;
+        if !folder.exists() {
+            info!("Creating {:?} to save {} in", folder, name);
+            fs::create_dir_all(folder)?;
+        }
+
+        let path = folder.join(name);
+        write_then_rename(&path, |partial| Ok(fs::write(partial, contents)?))?;
+        Ok(path)
+    }
+}
+
+/// One of the store's files for every root, like the favourites or the tags, kept in memory while the server runs.
+#[derive(Debug, Clone)]
+pub struct PerRoot<T> {
+    file: &'static str,
+    by_root: HashMap<String, T>,
+}
+
+/// What a [`PerRoot`] keeps for each root, by the path of each file from the root.
+pub trait ByPath {
+    fn has_path(&self, path: &str) -> bool;
+    fn rename_path(&mut self, from: &str, to: &str);
+}
+
+impl ByPath for BTreeSet<String> {
+    fn has_path(&self, path: &str) -> bool {
+        self.contains(path)
+    }
+
+    fn rename_path(&mut self, from: &str, to: &str) {
+        if self.remove(from) {
+            self.insert(to.to_string());
+        }
+    }
+}
+
+impl<V> ByPath for BTreeMap<String, V> {
+    fn has_path(&self, path: &str) -> bool {
+        self.contains_key(path)
+    }
+
+    fn rename_path(&mut self, from: &str, to: &str) {
+        if let Some(value) = self.remove(from) {
+            self.insert(to.to_string(), value);
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Default + Clone + ByPath> PerRoot<T> {
+    /// Nothing for any root yet.
+    pub fn new(file: &'static str) -> PerRoot<T> {
+        PerRoot {
+            file,
+            by_root: HashMap::new(),
+        }
+    }
+
+    /// A root whose file can't be read starts empty rather than stopping the server.
+    pub fn load(store: &Store, roots: &[String], file: &'static str) -> PerRoot<T> {
+        let by_root = roots
+            .iter()
+            .map(|root| {
+                let value = store.load(root, file).unwrap_or_else(|e| {
+                    warn!("Could not read {file} for {root}: {e}");
+                    T::default()
+                });
+                (root.clone(), value)
+            })
+            .collect();
+        PerRoot { file, by_root }
+    }
+
+    pub fn get(&self, root: &str) -> Option<&T> {
+        self.by_root.get(root)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &T)> {
+        self.by_root.iter()
+    }
+
+    /// Changes a copy of the root's value and saves it, only keeping the change if it was saved so the page never
+    /// shows something that will be lost.
+    pub fn update<R>(
+        &mut self,
+        store: &Store,
+        root: &str,
+        change: impl FnOnce(&mut T) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let mut value = self.by_root.get(root).cloned().unwrap_or_default();
+        let result = change(&mut value)?;
+        store.save(root, self.file, &value)?;
+        self.by_root.insert(root.to_string(), value);
+        Ok(result)
+    }
+
+    /// Changes every root's value in place, saving the ones `change` says it changed. These only catch up with what
+    /// has happened on disk, so one that can't be saved is logged and kept.
+    pub fn update_all(&mut self, store: &Store, mut change: impl FnMut(&str, &mut T) -> bool) {
+        for (root, value) in self.by_root.iter_mut() {
+            if change(root, value) {
+                if let Err(e) = store.save(root, self.file, value) {
+                    warn!("Could not save {} for {root}: {e}", self.file);
+                }
+            }
+        }
+    }
+
+    /// Moves what is kept about a file that has been moved or renamed to its new path.
+    pub fn rename_key(
+        &mut self,
+        store: &Store,
+        root: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<(), Error> {
+        if !self.get(root).is_some_and(|value| value.has_path(from)) {
+            return Ok(());
+        }
+        self.update(store, root, |value| {
+            value.rename_path(from, to);
+            Ok(())
+        })
+    }
+}
+
+/// Writes to a temporary name next to the target first and then renames it into place, so a half written file is
+/// never read or served, and a crash part way through can't lose what was there before. Each call gets its own
+/// temporary name so two at once for the same target can't write into the same file.
+pub fn write_then_rename(
+    target: &Path,
+    write: impl FnOnce(&Path) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.partial", Uuid::new_v4()));
+    let partial = target.with_file_name(name);
+
+    let result = write(&partial).and_then(|()| Ok(fs::rename(&partial, target)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+/// A folder name for a root inside the data folder. The full path is used so two roots with the same name don't
+/// share one.
+fn folder_name(root: &Path) -> String {
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    root.to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::error::Error;
+    use crate::store::{PerRoot, Store, STORE_FOLDER};
+
+    #[test]
+    fn save_and_load() {
+        let root = tempdir().unwrap();
+        let roots = vec![root.path().to_string_lossy().to_string()];
+        let store = Store::new(&roots, None);
+
+        let empty: BTreeSet<String> = store.load(&roots[0], "test.json").unwrap();
+        assert!(empty.is_empty());
+        assert!(!root.path().join(STORE_FOLDER).exists());
+
+        let saved: BTreeSet<String> = ["a.png".to_string()].into();
+        store.save(&roots[0], "test.json", &saved).unwrap();
+        assert!(root.path().join(STORE_FOLDER).join("test.json").exists());
+        let loaded: BTreeSet<String> = store.load(&roots[0], "test.json").unwrap();
+        assert_eq!(loaded, saved);
+    }
+
+    #[test]
+    fn per_root() {
+        let root = tempdir().unwrap();
+        let roots = vec![root.path().to_string_lossy().to_string()];
+        let store = Store::new(&roots, None);
+        fs::create_dir(root.path().join(STORE_FOLDER)).unwrap();
+        fs::write(root.path().join(STORE_FOLDER).join("test.json"), "not json").unwrap();
+
+        // Unreadable, so it starts empty.
+        let mut paths: PerRoot<BTreeSet<String>> = PerRoot::load(&store, &roots, "test.json");
+        assert_eq!(paths.get(&roots[0]), Some(&BTreeSet::new()));
+
+        paths
+            .update(&store, &roots[0], |paths| {
+                paths.insert("a.png".to_string());
+                Ok(())
+            })
+            .unwrap();
+        // A change that fails isn't kept.
+        assert!(paths
+            .update(&store, &roots[0], |paths| {
+                paths.insert("b.png".to_string());
+                Err::<(), _>(Error::InvalidName("b.png".to_string()))
+            })
+            .is_err());
+        paths
+            .rename_key(&store, &roots[0], "a.png", "moved/a.png")
+            .unwrap();
+
+        let loaded: PerRoot<BTreeSet<String>> = PerRoot::load(&store, &roots, "test.json");
+        let expected: BTreeSet<String> = ["moved/a.png".to_string()].into();
+        assert_eq!(loaded.get(&roots[0]), Some(&expected));
+        assert_eq!(paths.get(&roots[0]), Some(&expected));
+    }
+
+    #[test]
+    fn data_dir() {
+        let root = tempdir().unwrap();
+        let data = tempdir().unwrap();
+        fs::write(root.path().join("single.png"), b"").unwrap();
+        let roots = vec![
+            root.path().to_string_lossy().to_string(),
+            root.path().join("single.png").to_string_lossy().to_string(),
+        ];
+
+        let store = Store::new(&roots, Some(data.path()));
+        let first = store.folder(&roots[0]).unwrap();
+        let second = store.folder(&roots[1]).unwrap();
+        assert!(first.starts_with(data.path()));
+        assert!(second.starts_with(data.path()));
+        assert_ne!(first, second);
+
+        let store = Store::new(&roots, None);
+        assert_eq!(
+            store.folder(&roots[1]).unwrap(),
+            root.path().join(STORE_FOLDER).join("single.png")
+        );
+        assert!(store.folder("elsewhere").is_err());
+    }
+}
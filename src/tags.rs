@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::image_info::ImageInfo;
+use crate::store::{PerRoot, Store};
+
+pub const TAGS_FILE: &str = "tags.json";
+
+/// The tags and albums of one file. The hash and size are of the contents, so the entry can be found again if the
+/// file is renamed or moved somewhere else under the root.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaggedFile {
+    pub hash: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub albums: BTreeSet<String>,
+}
+
+/// The tagged files under each root, by their path from the root.
+#[derive(Debug, Clone)]
+pub struct Tags {
+    by_root: PerRoot<BTreeMap<String, TaggedFile>>,
+}
+
+/// A tag or album and how many files have it.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct LabelCount {
+    pub name: String,
+    pub count: usize,
+}
+
+impl Default for Tags {
+    fn default() -> Tags {
+        Tags {
+            by_root: PerRoot::new(TAGS_FILE),
+        }
+    }
+}
+
+impl Tags {
+    pub fn load(store: &Store, roots: &[String]) -> Tags {
+        Tags {
+            by_root: PerRoot::load(store, roots, TAGS_FILE),
+        }
+    }
+
+    /// Sets the `tags` and `albums` fields of each image after a scan. Tagged files that have gone are looked for by
+    /// their contents first, and if found the new path is saved.
+    pub fn mark(&mut self, store: &Store, images: &mut [ImageInfo]) {
+        self.by_root
+            .update_all(store, |root, files| follow_renames(root, files, images));
+
+        for image in images.iter_mut() {
+            self.apply(image);
+        }
+    }
+
+    fn apply(&self, image: &mut ImageInfo) {
+        match self
+            .by_root
+            .get(&image.root)
+            .and_then(|files| files.get(&image.relative_path))
+        {
+            Some(file) => {
+                image.tags = file.tags.iter().cloned().collect();
+                image.albums = file.albums.iter().cloned().collect();
+            }
+            None => {
+                image.tags = Vec::new();
+                image.albums = Vec::new();
+            }
+        }
+    }
+
+    /// Adds or removes a tag, saving the root's tags straight away and updating the image to match.
+    pub fn set_tag(
+        &mut self,
+        store: &Store,
+        image: &mut ImageInfo,
+        tag: &str,
+        tagged: bool,
+    ) -> Result<(), Error> {
+        let tag = check_tag(tag)?;
+        self.update(store, image, |file| {
+            if tagged {
+                file.tags.insert(tag);
+            } else {
+                file.tags.remove(&tag);
+            }
+        })
+    }
+
+    /// Adds or removes the image from an album, saving straight away. An album with nothing left in it is gone.
+    pub fn set_album(
+        &mut self,
+        store: &Store,
+        image: &mut ImageInfo,
+        album: &str,
+        included: bool,
+    ) -> Result<(), Error> {
+        let album = check_album(album)?;
+        self.update(store, image, |file| {
+            if included {
+                file.albums.insert(album);
+            } else {
+                file.albums.remove(&album);
+            }
+        })
+    }
+
+    fn update(
+        &mut self,
+        store: &Store,
+        image: &mut ImageInfo,
+        change: impl FnOnce(&mut TaggedFile),
+    ) -> Result<(), Error> {
+        self.by_root.update(store, &image.root, |files| {
+            let size = fs::metadata(&image.path)?.len();
+            let file = files.entry(image.relative_path.clone()).or_default();
+            if file.hash.is_empty() || file.size != size {
+                file.hash = content_hash(&image.path)?;
+                file.size = size;
+            }
+            change(file);
+            if file.tags.is_empty() && file.albums.is_empty() {
+                files.remove(&image.relative_path);
+            }
+            Ok(())
+        })?;
+        self.apply(image);
+        Ok(())
+    }
+
+    /// Keeps the tags of a file that has been moved or renamed, rather than waiting for the next scan to find it.
+    pub fn moved(&mut self, store: &Store, image: &ImageInfo, old_path: &str) -> Result<(), Error> {
+        self.by_root
+            .rename_key(store, &image.root, old_path, &image.relative_path)
+    }
+
+    pub fn tags(&self) -> Vec<LabelCount> {
+        self.count(|file| &file.tags)
+    }
+
+    pub fn albums(&self) -> Vec<LabelCount> {
+        self.count(|file| &file.albums)
+    }
+
+    /// Across every root, in name order.
+    fn count(&self, labels: impl Fn(&TaggedFile) -> &BTreeSet<String>) -> Vec<LabelCount> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for file in self.by_root.iter().flat_map(|(_, files)| files.values()) {
+            for label in labels(file) {
+                *counts.entry(label).or_default() += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(name, count)| LabelCount {
+                name: name.to_string(),
+                count,
+            })
+            .collect()
+    }
+}
+
+/// Moves the entries of files that are no longer where they were to a file with the same contents that has no entry of
+/// its own. Only files of the same size are hashed. Returns whether anything moved.
+fn follow_renames(
+    root: &str,
+    files: &mut BTreeMap<String, TaggedFile>,
+    images: &[ImageInfo],
+) -> bool {
+    let present: HashMap<&str, &ImageInfo> = images
+        .iter()
+        .filter(|i| i.root == root)
+        .map(|i| (i.relative_path.as_str(), i))
+        .collect();
+    let missing: Vec<String> = files
+        .keys()
+        .filter(|path| !present.contains_key(path.as_str()))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        return false;
+    }
+
+    let mut hashes: HashMap<&str, Option<String>> = HashMap::new();
+    let mut moved = false;
+    for old_path in missing {
+        let wanted = &files[&old_path];
+        let found = present.values().find(|image| {
+            !files.contains_key(&image.relative_path)
+                && fs::metadata(&image.path).is_ok_and(|m| m.len() == wanted.size)
+                && hashes
+                    .entry(&image.relative_path)
+                    .or_insert_with(|| content_hash(&image.path).ok())
+                    .as_ref()
+                    .is_some_and(|hash| *hash == wanted.hash)
+        });
+
+        if let Some(image) = found {
+            info!("{root}: {old_path} has moved to {}", image.relative_path);
+            let file = files.remove(&old_path).expect("the path came from the map");
+            files.insert(image.relative_path.clone(), file);
+            moved = true;
+        }
+    }
+    moved
+}
+
+/// The sha256 of the file contents, in hex.
+pub fn content_hash(path: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Tags are single words so they can be used in filters, `tag:beach,sunset`.
+fn check_tag(tag: &str) -> Result<String, Error> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c.is_control() || c == ',') {
+        return Err(Error::InvalidName(format!(
+            "tags can't be empty or have spaces or commas in them, not {tag:?}"
+        )));
+    }
+    Ok(tag.to_string())
+}
+
+fn check_album(album: &str) -> Result<String, Error> {
+    let album = album.trim();
+    if album.is_empty() || album.contains(char::is_control) {
+        return Err(Error::InvalidName(format!(
+            "albums need a name, not {album:?}"
+        )));
+    }
+    Ok(album.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::store::Store;
+    use crate::tags::{LabelCount, Tags};
+    use crate::tree::tests::test_image;
+
+    #[test]
+    fn tags_and_albums() {
+        let root = tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        fs::write(root.path().join("a.png"), b"first").unwrap();
+        fs::write(root.path().join("b.png"), b"second").unwrap();
+        let roots = vec![root_path.clone()];
+        let store = Store::new(&roots, None);
+
+        let mut a = test_image(&root_path, "a.png");
+        let mut b = test_image(&root_path, "b.png");
+        let mut tags = Tags::load(&store, &roots);
+        tags.set_tag(&store, &mut a, "beach", true).unwrap();
+        tags.set_tag(&store, &mut b, "beach", true).unwrap();
+        tags.set_tag(&store, &mut b, "snow", true).unwrap();
+        tags.set_album(&store, &mut a, "Summer 2024", true).unwrap();
+        tags.set_tag(&store, &mut b, "beach", false).unwrap();
+        assert_eq!(a.tags, vec!["beach"]);
+        assert_eq!(a.albums, vec!["Summer 2024"]);
+        assert_eq!(b.tags, vec!["snow"]);
+
+        assert!(tags.set_tag(&store, &mut a, "two words", true).is_err());
+        assert!(tags.set_album(&store, &mut a, " ", true).is_err());
+
+        // A fresh load, like after a restart.
+        let mut tags = Tags::load(&store, &roots);
+        let mut images = vec![
+            test_image(&root_path, "a.png"),
+            test_image(&root_path, "b.png"),
+        ];
+        tags.mark(&store, &mut images);
+        assert_eq!(images[0].tags, vec!["beach"]);
+        assert_eq!(images[1].tags, vec!["snow"]);
+        assert_eq!(
+            tags.albums(),
+            vec![LabelCount {
+                name: "Summer 2024".to_string(),
+                count: 1
+            }]
+        );
+        assert_eq!(tags.tags().len(), 2);
+    }
+
+    #[test]
+    fn renamed_files_keep_their_tags() {
+        let root = tempdir().unwrap();
+        let root_path = root.path().to_string_lossy().to_string();
+        fs::write(root.path().join("a.png"), b"first").unwrap();
+        fs::write(root.path().join("b.png"), b"other").unwrap();
+        let roots = vec![root_path.clone()];
+        let store = Store::new(&roots, None);
+
+        let mut tags = Tags::load(&store, &roots);
+        tags.set_tag(&store, &mut test_image(&root_path, "a.png"), "beach", true)
+            .unwrap();
+
+        fs::create_dir(root.path().join("sub")).unwrap();
+        fs::rename(root.path().join("a.png"), root.path().join("sub/c.png")).unwrap();
+
+        let mut tags = Tags::load(&store, &roots);
+        let mut images = vec![
+            test_image(&root_path, "b.png"),
+            test_image(&root_path, "sub/c.png"),
+        ];
+        tags.mark(&store, &mut images);
+        assert!(images[0].tags.is_empty());
+        assert_eq!(images[1].tags, vec!["beach"]);
+
+        // The new path was saved.
+        let mut images = vec![test_image(&root_path, "sub/c.png")];
+        Tags::load(&store, &roots).mark(&store, &mut images);
+        assert_eq!(images[0].tags, vec!["beach"]);
+    }
+}
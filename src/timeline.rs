@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::group::GroupBy;
+use crate::image_info::ImageInfo;
+
+/// How much time each part of the timeline covers.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Day,
+    #[default]
+    Month,
+}
+
+impl Bucket {
+    fn group(&self) -> GroupBy {
+        match self {
+            Bucket::Day => GroupBy::Day,
+            Bucket::Month => GroupBy::Month,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineBucket {
+    /// The date the bucket covers, `2024-03` or `2024-03-15`.
+    pub key: String,
+    pub label: String,
+    pub count: usize,
+    /// How far down the page the first image in the bucket is, from the layout.
+    pub offset: u64,
+    /// The first image in the bucket, to scroll to.
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Timeline {
+    pub total_height: u64,
+    /// In the order they first appear in the page.
+    pub buckets: Vec<TimelineBucket>,
+}
+
+/// Counts the images in each day or month. The images need to have been laid out already so the offsets are right.
+pub fn timeline(images: &[ImageInfo], bucket: Bucket) -> Timeline {
+    let group = bucket.group();
+    let mut buckets: Vec<TimelineBucket> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for image in images {
+        let key = group.key(image);
+        match index.get(&key) {
+            Some(i) => buckets[*i].count += 1,
+            None => {
+                index.insert(key.clone(), buckets.len());
+                buckets.push(TimelineBucket {
+                    key,
+                    label: group.label(image),
+                    count: 1,
+                    offset: image.height_before,
+                    url: image.url.clone(),
+                });
+            }
+        }
+    }
+
+    Timeline {
+        total_height: images
+            .last()
+            .map(|i| i.height_before + i.layout_height())
+            .unwrap_or(0),
+        buckets,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use crate::image_info::ImageInfo;
+    use crate::timeline::{timeline, Bucket};
+    use crate::tree::tests::simple_image;
+    use crate::IMAGE_OFFSET;
+
+    fn image(url: &str, year: i32, month: u32, day: u32, height_before: u64) -> ImageInfo {
+        let mut image = simple_image(100);
+        image.url = url.to_string();
+        image.date = Local
+            .with_ymd_and_hms(year, month, day, 12, 0, 0)
+            .unwrap()
+            .into();
+        image.height_before = height_before;
+        image
+    }
+
+    #[test]
+    fn month_buckets() {
+        let step = 100 + IMAGE_OFFSET;
+        let images = vec![
+            image("a", 2024, 3, 15, 0),
+            image("b", 2024, 3, 1, step),
+            image("c", 2024, 1, 9, 2 * step),
+            // out of order, counted with the first march bucket
+            image("d", 2024, 3, 2, 3 * step),
+        ];
+
+        let result = timeline(&images, Bucket::Month);
+        assert_eq!(result.total_height, 4 * step);
+
+        let buckets: Vec<(&str, usize, u64, &str)> = result
+            .buckets
+            .iter()
+            .map(|b| (b.key.as_str(), b.count, b.offset, b.url.as_str()))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![("2024-03", 3, 0, "a"), ("2024-01", 1, 2 * step, "c")]
+        );
+        assert_eq!(result.buckets[0].label, "March 2024");
+
+        let days = timeline(&images, Bucket::Day);
+        assert_eq!(days.buckets.len(), 4);
+        assert_eq!(days.buckets[1].key, "2024-03-01");
+    }
+}
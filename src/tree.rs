@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{error::Error, image_info::ImageInfo, IMAGE_OFFSET};
+use crate::{error::Error, image_info::ImageInfo};
 
 #[derive(Debug, Clone)]
 pub enum TreeNode {
@@ -9,7 +9,7 @@ pub enum TreeNode {
 }
 
 impl TreeNode {
-    pub fn tree_from_images(images: &Vec<ImageInfo>) -> Option<TreeNode> {
+    pub fn tree_from_images(images: &[ImageInfo]) -> Option<TreeNode> {
         if images.is_empty() {
             return None;
         }
@@ -18,7 +18,7 @@ impl TreeNode {
         println!("Levels: {}", levels);
 
         let root = TreeNode::split(&TreeNode::Leaf {
-            images: images.clone(),
+            images: images.to_vec(),
         });
 
         Some(root)
@@ -52,7 +52,22 @@ impl TreeNode {
     pub fn height(&self) -> u64 {
         match self {
             TreeNode::Branch { a, b } => a.height() + b.height(),
-            TreeNode::Leaf { images } => images.iter().map(|i| i.height + IMAGE_OFFSET).sum(),
+            TreeNode::Leaf { images } => images.iter().map(|i| i.layout_height()).sum(),
+        }
+    }
+
+    /// Swaps in the changed copy of an image, found by its url, for changes that don't move it. Returns whether it was
+    /// there.
+    pub fn update_image(&mut self, image: &ImageInfo) -> bool {
+        match self {
+            TreeNode::Branch { a, b } => a.update_image(image) || b.update_image(image),
+            TreeNode::Leaf { images } => match images.iter_mut().find(|i| i.url == image.url) {
+                Some(existing) => {
+                    *existing = image.clone();
+                    true
+                }
+                None => false,
+            },
         }
     }
 
@@ -117,23 +132,59 @@ impl From<&TreeNode> for TreeNodeLayer {
 
 #[cfg(test)]
 pub mod tests {
-    use std::time::SystemTime;
+    use std::{fs, path::PathBuf, time::SystemTime};
 
-    use crate::{image_info::ImageInfo, tree::TreeNode, IMAGE_OFFSET};
+    use crate::{image_info::ImageInfo, sniff::MediaType, tree::TreeNode, IMAGE_OFFSET};
 
-    fn simple_image(height: u64) -> ImageInfo {
+    pub fn simple_image(height: u64) -> ImageInfo {
         ImageInfo {
             url: "0000-0000-33333-33333.png".to_string(),
             source: "fish.png".to_string(),
+            path: PathBuf::from("fish.png"),
+            root: "".to_string(),
+            relative_path: "fish.png".to_string(),
+            media_type: MediaType::Png,
             date: SystemTime::now(),
             is_video: false,
+            animated: false,
+            frame_count: 1,
+            duration_ms: 0,
+            codec: None,
+            frame_rate: None,
+            has_audio: false,
             width: 600,
-            height: height,
+            height,
+            rating: None,
+            label: None,
+            favourite: false,
+            tags: Vec::new(),
+            albums: Vec::new(),
+            decision: None,
+            header: None,
             height_before: 0,
             height_after: 0,
         }
     }
 
+    /// An image at `relative_path` under `root`, with its full path to match.
+    pub fn test_image(root: &str, relative_path: &str) -> ImageInfo {
+        let mut image = simple_image(100);
+        image.root = root.to_string();
+        image.relative_path = relative_path.to_string();
+        image.path = [root, relative_path].iter().collect();
+        image.source = image.path.to_string_lossy().to_string();
+        image.url = relative_path.to_string();
+        image
+    }
+
+    /// The same, with a file written for it holding its own path so every file is different.
+    pub fn test_file(root: &str, relative_path: &str) -> ImageInfo {
+        let image = test_image(root, relative_path);
+        fs::create_dir_all(image.path.parent().unwrap()).unwrap();
+        fs::write(&image.path, relative_path).unwrap();
+        image
+    }
+
     #[test]
     fn test_build_tree_single_image() {
         let image_list = vec![simple_image(400)];
@@ -166,7 +217,7 @@ pub mod tests {
                 assert_eq!(images[0].height, 400);
                 assert_eq!(images[1].height, 500);
             }
-            _ => assert!(false, "invalid node"),
+            _ => panic!("invalid node"),
         }
         match node.path("b").unwrap() {
             TreeNode::Leaf { images } => {
@@ -174,7 +225,7 @@ pub mod tests {
                 assert_eq!(images[0].height, 300);
                 assert_eq!(images[1].height, 600);
             }
-            _ => assert!(false, "invalid node"),
+            _ => panic!("invalid node"),
         }
     }
 
@@ -197,7 +248,27 @@ pub mod tests {
                 assert_eq!(images[0].height, 900);
                 assert_eq!(images[1].height, 1000);
             }
-            _ => assert!(false, "invalid node type"),
+            _ => panic!("invalid node type"),
+        }
+    }
+
+    #[test]
+    fn update_image() {
+        let mut images: Vec<ImageInfo> = (0..5).map(|_| simple_image(100)).collect();
+        for (i, image) in images.iter_mut().enumerate() {
+            image.url = format!("{i}.png");
+        }
+        let mut tree = TreeNode::tree_from_images(&images).unwrap();
+
+        images[3].favourite = true;
+        assert!(tree.update_image(&images[3]));
+        match tree.path("bb").unwrap() {
+            TreeNode::Leaf { images } => assert!(images[0].favourite),
+            _ => panic!("invalid node"),
         }
+
+        let mut gone = simple_image(100);
+        gone.url = "gone.png".to_string();
+        assert!(!tree.update_image(&gone));
     }
 }
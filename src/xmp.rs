@@ -0,0 +1,657 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::name::{LocalName, Namespace, QName, ResolveResult};
+use quick_xml::reader::NsReader;
+use quick_xml::Writer;
+
+use crate::error::Error;
+use crate::sniff::MediaType;
+use crate::store::write_then_rename;
+
+const XMP_NAMESPACE: &str = "http://ns.adobe.com/xap/1.0/";
+const RDF_NAMESPACE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// What starts the APP1 segment holding a jpeg's xmp.
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// The keyword of the iTXt chunk holding a png's xmp.
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+
+/// The star rating and colour label given to a file by Lightroom, darktable and the like.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XmpInfo {
+    /// 1 to 5 stars, 0 for unrated and -1 for rejected.
+    pub rating: Option<i8>,
+    pub label: Option<String>,
+}
+
+impl XmpInfo {
+    fn is_empty(&self) -> bool {
+        self.rating.is_none() && self.label.is_none()
+    }
+}
+
+/// Reads the rating and label from a sidecar next to the file, or failing that from xmp embedded in the file itself.
+/// A sidecar that can't be read is logged and skipped rather than leaving the file out.
+pub fn read_xmp(path: &Path, media_type: MediaType) -> XmpInfo {
+    if let Some(sidecar) = find_sidecar(path) {
+        match fs::read(&sidecar)
+            .map_err(Error::from)
+            .and_then(|contents| parse_xmp(&contents))
+        {
+            Ok(info) if !info.is_empty() => return info,
+            Ok(_) => {}
+            Err(e) => warn!("Could not read {:?}: {}", sidecar, e),
+        }
+    }
+
+    let embedded = match media_type {
+        MediaType::Jpeg => jpeg_xmp(path),
+        MediaType::Png => png_xmp(path),
+        _ => return XmpInfo::default(),
+    };
+    embedded
+        .and_then(|packet| parse_xmp(&packet))
+        .unwrap_or_default()
+}
+
+/// darktable names sidecars `photo.jpg.xmp` and Lightroom `photo.xmp`, in that order.
+fn sidecar_names(path: &Path) -> [PathBuf; 2] {
+    let mut full = path.as_os_str().to_owned();
+    full.push(".xmp");
+    [PathBuf::from(full), path.with_extension("xmp")]
+}
+
+fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    sidecar_names(path).into_iter().find(|p| p.is_file())
+}
+
+/// The sidecars that belong to a file and where they should go if it moves to `to`. A Lightroom style `photo.xmp` is
+/// left where it is if another file, like the raw `photo.cr2`, shares it.
+pub fn sidecar_moves(from: &Path, to: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let [full_from, short_from] = sidecar_names(from);
+    let [full_to, short_to] = sidecar_names(to);
+    let mut moves = Vec::new();
+    if full_from.is_file() {
+        moves.push((full_from, full_to));
+    }
+    if short_from.is_file() && !shares_stem(from) {
+        moves.push((short_from, short_to));
+    }
+    moves
+}
+
+fn shares_stem(path: &Path) -> bool {
+    let (Some(parent), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return false;
+    };
+    let Ok(entries) = fs::read_dir(parent) else {
+        // can't tell, so leave the sidecar alone
+        return true;
+    };
+    entries.flatten().any(|entry| {
+        let other = entry.path();
+        other != path
+            && other.file_stem() == Some(stem)
+            && other
+                .extension()
+                .is_some_and(|e| !e.eq_ignore_ascii_case("xmp"))
+    })
+}
+
+/// The packet from the APP1 segment it's kept in, walking the segments up to the image data rather than searching
+/// the whole file.
+fn jpeg_xmp(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut marker = [0; 2];
+    file.read_exact(&mut marker)?;
+    if marker != [0xff, 0xd8] {
+        return Err(no_xmp());
+    }
+
+    loop {
+        file.read_exact(&mut marker)?;
+        // any number of 0xff can pad out a marker
+        while marker == [0xff, 0xff] {
+            file.read_exact(&mut marker[1..])?;
+        }
+        match marker {
+            // the start of the image data, or the end of the image
+            [0xff, 0xda | 0xd9] => return Err(no_xmp()),
+            // markers without a segment
+            [0xff, 0x01 | 0xd0..=0xd7] => continue,
+            [0xff, _] => {}
+            _ => return Err(no_xmp()),
+        }
+
+        let mut length = [0; 2];
+        file.read_exact(&mut length)?;
+        let length = usize::from(u16::from_be_bytes(length))
+            .checked_sub(2)
+            .ok_or_else(no_xmp)?;
+        if marker[1] == 0xe1 && length > JPEG_XMP_HEADER.len() {
+            let mut header = vec![0; JPEG_XMP_HEADER.len()];
+            file.read_exact(&mut header)?;
+            if header == JPEG_XMP_HEADER {
+                let mut packet = vec![0; length - header.len()];
+                file.read_exact(&mut packet)?;
+                return Ok(packet);
+            }
+            file.seek_relative((length - header.len()) as i64)?;
+        } else {
+            file.seek_relative(length as i64)?;
+        }
+    }
+}
+
+/// The packet from the iTXt chunk it's kept in, walking the chunks up to the image data rather than searching the
+/// whole file.
+fn png_xmp(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut signature = [0; 8];
+    file.read_exact(&mut signature)?;
+    if &signature != b"\x89PNG\r\n\x1a\n" {
+        return Err(no_xmp());
+    }
+
+    loop {
+        let mut header = [0; 8];
+        file.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        match &header[4..] {
+            b"IDAT" | b"IEND" => return Err(no_xmp()),
+            b"iTXt" if length > PNG_XMP_KEYWORD.len() => {
+                let mut keyword = vec![0; PNG_XMP_KEYWORD.len()];
+                file.read_exact(&mut keyword)?;
+                if keyword == PNG_XMP_KEYWORD {
+                    let mut text = vec![0; length - keyword.len()];
+                    file.read_exact(&mut text)?;
+                    return itxt_text(&text);
+                }
+                file.seek_relative((length - keyword.len()) as i64)?;
+            }
+            _ => file.seek_relative(length as i64)?,
+        }
+        // the crc
+        file.seek_relative(4)?;
+    }
+}
+
+/// What follows the keyword is the compression flag and method, then the language and translated keyword ending in
+/// nuls, then the text. Xmp is never meant to be compressed.
+fn itxt_text(contents: &[u8]) -> Result<Vec<u8>, Error> {
+    let [0, _, rest @ ..] = contents else {
+        return Err(no_xmp());
+    };
+    let mut parts = rest.splitn(3, |b| *b == 0);
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(_), Some(text)) => Ok(text.to_vec()),
+        _ => Err(no_xmp()),
+    }
+}
+
+fn no_xmp() -> Error {
+    Error::Xmp("no xmp packet".to_string())
+}
+
+/// The local part of a name in the xmp namespace, whatever prefix the file binds it to.
+fn xmp_name<'n>((namespace, local): (ResolveResult, LocalName<'n>)) -> Option<&'n [u8]> {
+    match namespace {
+        ResolveResult::Bound(Namespace(ns)) if ns == XMP_NAMESPACE.as_bytes() => {
+            Some(local.into_inner())
+        }
+        _ => None,
+    }
+}
+
+fn is_description(reader: &NsReader<&[u8]>, tag: &BytesStart) -> bool {
+    matches!(
+        reader.resolve_element(tag.name()),
+        (ResolveResult::Bound(Namespace(ns)), local)
+            if ns == RDF_NAMESPACE.as_bytes() && local.as_ref() == b"Description"
+    )
+}
+
+/// Ratings and labels can be attributes of an `rdf:Description` or elements inside it, both are used.
+fn parse_xmp(contents: &[u8]) -> Result<XmpInfo, Error> {
+    let mut reader = NsReader::from_reader(contents);
+    let mut info = XmpInfo::default();
+    let mut in_element: Option<Vec<u8>> = None;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) if is_description(&reader, &e) => {
+                for attribute in e.attributes() {
+                    let attribute = attribute.map_err(quick_xml::Error::from)?;
+                    let value = attribute.unescape_value()?;
+                    match xmp_name(reader.resolve_attribute(attribute.key)) {
+                        Some(b"Rating") => info.rating = parse_rating(&value),
+                        Some(b"Label") if !value.is_empty() => info.label = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+            Event::Start(e) => {
+                in_element = xmp_name(reader.resolve_element(e.name()))
+                    .filter(|name| matches!(*name, b"Rating" | b"Label"))
+                    .map(<[u8]>::to_vec);
+            }
+            Event::Text(text) => {
+                let value = text.decode().map_err(quick_xml::Error::from)?;
+                let value = value.trim();
+                match in_element.as_deref() {
+                    Some(b"Rating") => info.rating = parse_rating(value),
+                    Some(b"Label") if !value.is_empty() => info.label = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            Event::End(_) => in_element = None,
+            Event::Eof => return Ok(info),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Some programs write ratings as decimals.
+fn parse_rating(value: &str) -> Option<i8> {
+    let rating: f64 = value.trim().parse().ok()?;
+    Some(rating.round().clamp(-1.0, 5.0) as i8)
+}
+
+/// Saves a rating to the file's sidecar, changing only the rating if there already is one. Files themselves are never
+/// written to, so a rating embedded in one is overridden by the new sidecar. Returns the sidecar's path.
+pub fn write_rating(path: &Path, rating: i8) -> Result<PathBuf, Error> {
+    if !(-1..=5).contains(&rating) {
+        return Err(Error::InvalidRating(rating.to_string()));
+    }
+
+    let (sidecar, contents) = match find_sidecar(path) {
+        Some(sidecar) => {
+            let contents = set_rating(&fs::read(&sidecar)?, rating)?;
+            (sidecar, contents)
+        }
+        None => {
+            let [sidecar, _] = sidecar_names(path);
+            (sidecar, new_sidecar(rating).into_bytes())
+        }
+    };
+
+    write_then_rename(&sidecar, |partial| Ok(fs::write(partial, contents)?))?;
+    Ok(sidecar)
+}
+
+fn new_sidecar(rating: i8) -> String {
+    format!(
+        r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="{RDF_NAMESPACE}">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="{XMP_NAMESPACE}"
+    xmp:Rating="{rating}"/>
+ </rdf:RDF>
+</x:xmpmeta>
+"#
+    )
+}
+
+/// Copies the xmp, replacing the rating wherever it is. If there wasn't one it's added to the first `rdf:Description`.
+fn set_rating(contents: &[u8], rating: i8) -> Result<Vec<u8>, Error> {
+    let had_rating = parse_xmp(contents)?.rating.is_some();
+    let rating_text = rating.to_string();
+
+    let mut reader = NsReader::from_reader(contents);
+    let mut writer = Writer::new(Vec::new());
+    let mut buf = Vec::new();
+    let mut added = had_rating;
+    let mut in_rating = false;
+
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) if is_description(&reader, e) => {
+                let description = if !added {
+                    added = true;
+                    add_rating_attribute(&reader, e, &rating_text)
+                } else {
+                    match rating_key(&reader, e)? {
+                        Some(key) => replace_rating_attribute(e, &key, &rating_text)?,
+                        None => e.to_owned(),
+                    }
+                };
+                match event {
+                    Event::Start(_) => writer.write_event(Event::Start(description))?,
+                    _ => writer.write_event(Event::Empty(description))?,
+                }
+            }
+            Event::Start(ref e)
+                if xmp_name(reader.resolve_element(e.name())) == Some(b"Rating") =>
+            {
+                in_rating = true;
+                writer.write_event(event.borrow())?;
+                writer.write_event(Event::Text(BytesText::new(&rating_text)))?;
+            }
+            Event::End(_) if in_rating => {
+                in_rating = false;
+                writer.write_event(event.borrow())?;
+            }
+            // the old rating
+            _ if in_rating => {}
+            Event::Eof => break,
+            _ => writer.write_event(event.borrow())?,
+        }
+        buf.clear();
+    }
+
+    if !added {
+        return Err(Error::Xmp(
+            "no rdf:Description to add a rating to".to_string(),
+        ));
+    }
+    Ok(writer.into_inner())
+}
+
+/// Edits the raw text of the tag so the rest of it keeps its layout. The rating goes in as `xmp:Rating`, declaring
+/// the prefix if it isn't already, unless the file uses `xmp` for something else.
+fn add_rating_attribute(
+    reader: &NsReader<&[u8]>,
+    tag: &BytesStart,
+    rating: &str,
+) -> BytesStart<'static> {
+    let mut content = String::from_utf8_lossy(tag).to_string();
+    let mut number = 0;
+    let prefix = loop {
+        let prefix = match number {
+            0 => "xmp".to_string(),
+            _ => format!("xmp{number}"),
+        };
+        let key = format!("{prefix}:Rating");
+        match reader.resolve_attribute(QName(key.as_bytes())) {
+            (ResolveResult::Bound(Namespace(ns)), _) if ns == XMP_NAMESPACE.as_bytes() => {
+                break prefix
+            }
+            (ResolveResult::Unknown(_), _) => {
+                content.push_str(&format!(r#" xmlns:{prefix}="{XMP_NAMESPACE}""#));
+                break prefix;
+            }
+            _ => number += 1,
+        }
+    };
+    content.push_str(&format!(r#" {prefix}:Rating="{rating}""#));
+    BytesStart::from_content(content, tag.name().as_ref().len())
+}
+
+/// The name the rating attribute has in this file, if the tag has one.
+fn rating_key(reader: &NsReader<&[u8]>, tag: &BytesStart) -> Result<Option<String>, Error> {
+    for attribute in tag.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        if xmp_name(reader.resolve_attribute(attribute.key)) == Some(b"Rating") {
+            return Ok(Some(
+                String::from_utf8_lossy(attribute.key.as_ref()).to_string(),
+            ));
+        }
+    }
+    Ok(None)
+}
+
+fn replace_rating_attribute(
+    tag: &BytesStart,
+    key: &str,
+    rating: &str,
+) -> Result<BytesStart<'static>, Error> {
+    let mut content = String::from_utf8_lossy(tag).to_string();
+    let key_start = format!("{key}=");
+    let quoted = content.find(&key_start).and_then(|start| {
+        let value_start = start + key_start.len() + 1;
+        let quote = content[..value_start].chars().last()?;
+        let length = content[value_start..].find(quote)?;
+        matches!(quote, '"' | '\'').then_some(value_start..value_start + length)
+    });
+    if let Some(value) = quoted {
+        content.replace_range(value, rating);
+        return Ok(BytesStart::from_content(content, tag.name().as_ref().len()));
+    }
+
+    // Laid out some other way, build it again with just the rating changed.
+    let mut rebuilt = BytesStart::new(String::from_utf8_lossy(tag.name().as_ref()).to_string());
+    for attribute in tag.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        if attribute.key.as_ref() == key.as_bytes() {
+            rebuilt.push_attribute((key, rating));
+        } else {
+            rebuilt.push_attribute(attribute);
+        }
+    }
+    Ok(rebuilt.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::sniff::MediaType;
+    use crate::xmp::{parse_xmp, read_xmp, set_rating, sidecar_moves, write_rating, XmpInfo};
+
+    /// Cut down from a Lightroom sidecar.
+    const LIGHTROOM: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 7.0-c000">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+    xmp:Rating="3"
+    xmp:Label="Red &amp; Blue"
+    crs:Exposure2012="+0.50">
+   <crs:ToneCurvePV2012>
+    <rdf:Seq>
+     <rdf:li>0, 0</rdf:li>
+    </rdf:Seq>
+   </crs:ToneCurvePV2012>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    /// Ratings as elements rather than attributes.
+    const ELEMENTS: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+   <xmp:Rating>4</xmp:Rating>
+   <xmp:Label>Green</xmp:Label>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    /// The older `xap` prefix for the same namespace.
+    const XAP_PREFIX: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:xap="http://ns.adobe.com/xap/1.0/" xap:Rating="2">
+   <xap:Label>Blue</xap:Label>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    /// An `xmp` prefix bound to something else isn't a rating.
+    const OTHER_NAMESPACE: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:xmp="http://example.com/" xmp:Rating="2"/>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    /// darktable, with no rating yet.
+    const UNRATED: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:darktable="http://darktable.sf.net/" darktable:xmp_version="5"/>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    fn info(rating: i8, label: Option<&str>) -> XmpInfo {
+        XmpInfo {
+            rating: Some(rating),
+            label: label.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_xmp(LIGHTROOM.as_bytes()).unwrap(),
+            info(3, Some("Red & Blue"))
+        );
+        assert_eq!(
+            parse_xmp(ELEMENTS.as_bytes()).unwrap(),
+            info(4, Some("Green"))
+        );
+        assert_eq!(parse_xmp(UNRATED.as_bytes()).unwrap(), XmpInfo::default());
+        assert_eq!(
+            parse_xmp(XAP_PREFIX.as_bytes()).unwrap(),
+            info(2, Some("Blue"))
+        );
+        assert_eq!(
+            parse_xmp(OTHER_NAMESPACE.as_bytes()).unwrap(),
+            XmpInfo::default()
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for (xmp, label) in [
+            (LIGHTROOM, Some("Red & Blue")),
+            (ELEMENTS, Some("Green")),
+            (UNRATED, None),
+            (XAP_PREFIX, Some("Blue")),
+            (OTHER_NAMESPACE, None),
+        ] {
+            let changed = set_rating(xmp.as_bytes(), -1).unwrap();
+            assert_eq!(parse_xmp(&changed).unwrap(), info(-1, label), "{xmp}");
+            let changed = set_rating(&changed, 5).unwrap();
+            assert_eq!(parse_xmp(&changed).unwrap(), info(5, label), "{xmp}");
+        }
+
+        // Everything else is left alone.
+        let changed = String::from_utf8(set_rating(LIGHTROOM.as_bytes(), 1).unwrap()).unwrap();
+        assert_eq!(
+            changed,
+            LIGHTROOM.replace(r#"xmp:Rating="3""#, r#"xmp:Rating="1""#)
+        );
+    }
+
+    #[test]
+    fn sidecars() {
+        let folder = tempdir().unwrap();
+        let photo = folder.path().join("photo.jpg");
+        fs::write(&photo, jpeg(ELEMENTS)).unwrap();
+        assert_eq!(read_xmp(&photo, MediaType::Jpeg), info(4, Some("Green")));
+
+        // A Lightroom sidecar wins over the embedded rating.
+        fs::write(folder.path().join("photo.xmp"), LIGHTROOM).unwrap();
+        assert_eq!(read_xmp(&photo, MediaType::Jpeg).rating, Some(3));
+
+        // An existing sidecar is updated in place.
+        let sidecar = write_rating(&photo, 2).unwrap();
+        assert_eq!(sidecar, folder.path().join("photo.xmp"));
+        assert_eq!(
+            read_xmp(&photo, MediaType::Jpeg),
+            info(2, Some("Red & Blue"))
+        );
+
+        // Otherwise one is made, named the darktable way.
+        let other = folder.path().join("other.png");
+        fs::write(&other, b"").unwrap();
+        assert_eq!(read_xmp(&other, MediaType::Png), XmpInfo::default());
+        let sidecar = write_rating(&other, 5).unwrap();
+        assert_eq!(sidecar, folder.path().join("other.png.xmp"));
+        assert_eq!(read_xmp(&other, MediaType::Png).rating, Some(5));
+
+        assert!(write_rating(&other, 6).is_err());
+    }
+
+    /// A jpeg with xmp embedded in it after an exif segment, cut down to the parts that matter.
+    fn jpeg(xmp: &str) -> Vec<u8> {
+        let mut jpeg = b"\xff\xd8\xff\xe1\x00\x08Exif\0\0".to_vec();
+        let app1 = [b"http://ns.adobe.com/xap/1.0/\0".as_slice(), xmp.as_bytes()].concat();
+        jpeg.extend([0xff, 0xe1]);
+        jpeg.extend((app1.len() as u16 + 2).to_be_bytes());
+        jpeg.extend(app1);
+        jpeg.extend(b"\xff\xda\x00\x02\xff\xd9");
+        jpeg
+    }
+
+    fn png(xmp: &str) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut chunk = |kind: &[u8], data: &[u8]| {
+            png.extend((data.len() as u32).to_be_bytes());
+            png.extend(kind);
+            png.extend(data);
+            // the crc isn't checked
+            png.extend([0; 4]);
+        };
+        chunk(b"IHDR", &[0; 13]);
+        chunk(
+            b"iTXt",
+            &[b"XML:com.adobe.xmp\0\0\0\0\0".as_slice(), xmp.as_bytes()].concat(),
+        );
+        chunk(b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn embedded() {
+        let folder = tempdir().unwrap();
+        let photo = folder.path().join("photo.jpg");
+        fs::write(&photo, jpeg(XAP_PREFIX)).unwrap();
+        assert_eq!(read_xmp(&photo, MediaType::Jpeg), info(2, Some("Blue")));
+
+        let graphic = folder.path().join("graphic.png");
+        fs::write(&graphic, png(LIGHTROOM)).unwrap();
+        assert_eq!(
+            read_xmp(&graphic, MediaType::Png),
+            info(3, Some("Red & Blue"))
+        );
+
+        // Only where the format keeps it, not anywhere in the file.
+        let mut after_image = jpeg(UNRATED);
+        after_image.extend(LIGHTROOM.as_bytes());
+        fs::write(&photo, after_image).unwrap();
+        assert_eq!(read_xmp(&photo, MediaType::Jpeg), XmpInfo::default());
+        fs::write(&photo, LIGHTROOM).unwrap();
+        assert_eq!(read_xmp(&photo, MediaType::Jpeg), XmpInfo::default());
+    }
+
+    #[test]
+    fn sidecars_move_with_their_file() {
+        let folder = tempdir().unwrap();
+        let from = folder.path().join("photo.jpg");
+        let to = folder.path().join("renamed.jpg");
+        fs::write(&from, b"").unwrap();
+        fs::write(folder.path().join("photo.jpg.xmp"), UNRATED).unwrap();
+        fs::write(folder.path().join("photo.xmp"), UNRATED).unwrap();
+
+        let moves = sidecar_moves(&from, &to);
+        assert_eq!(
+            moves,
+            vec![
+                (
+                    folder.path().join("photo.jpg.xmp"),
+                    folder.path().join("renamed.jpg.xmp")
+                ),
+                (
+                    folder.path().join("photo.xmp"),
+                    folder.path().join("renamed.xmp")
+                ),
+            ]
+        );
+
+        // The raw file still needs the shared one.
+        fs::write(folder.path().join("photo.cr2"), b"").unwrap();
+        assert_eq!(sidecar_moves(&from, &to).len(), 1);
+    }
+}